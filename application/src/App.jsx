@@ -1,12 +1,63 @@
-import React, { useCallback, useState } from 'react';
+import React, { useCallback, useEffect, useRef, useState } from 'react';
 import { useDropzone } from 'react-dropzone';
 import { invoke } from '@tauri-apps/api/tauri';
 import { open } from '@tauri-apps/api/dialog';
+import { listen } from '@tauri-apps/api/event';
+
+// Commands fail with { code, message } and, for integrity errors, the file and part
+function describeError(error) {
+	if (typeof error === 'string') return error;
+	switch (error.code) {
+		case 'expired':
+			return `${error.message}. Ask the sender for a new link.`;
+		case 'integrity':
+			return error.part
+				? `${error.file}, part ${error.part}: ${error.message}`
+				: `${error.file}: ${error.message}`;
+		case 'refused':
+			return `${error.message}. The share may have been tampered with.`;
+		case 'internal':
+			return `${error.message}. This is a bug, please report it.`;
+		case 'cancelled':
+			return 'Cancelled';
+		default:
+			return error.message;
+	}
+}
 
 function App() {
 	const [files, setFiles] = useState([]);
 	const [responseTitle, setResponseTitle] = useState('');
+	const [uploadError, setUploadError] = useState('');
 	const [rebuildTitle, setRebuildTitle] = useState('');
+	const [compression, setCompression] = useState('none');
+	const [compressionLevel, setCompressionLevel] = useState('');
+	const [encoding, setEncoding] = useState('');
+	const [parityShards, setParityShards] = useState('');
+	const [mirrorPasteRs, setMirrorPasteRs] = useState(false);
+	const [mirrorDirectory, setMirrorDirectory] = useState('');
+	const [keepAlive, setKeepAlive] = useState(false);
+	const [rebuildReports, setRebuildReports] = useState([]);
+	const [destination, setDestination] = useState('');
+	const [conflict, setConflict] = useState('rename');
+	const [rebuildError, setRebuildError] = useState('');
+	const [shareInfo, setShareInfo] = useState(null);
+	const [selectedIds, setSelectedIds] = useState([]);
+	const [probeParts, setProbeParts] = useState(false);
+	const [history, setHistory] = useState([]);
+	const [historyText, setHistoryText] = useState('');
+	const [historyBackend, setHistoryBackend] = useState('');
+	const [historyFrom, setHistoryFrom] = useState('');
+	const [historyTo, setHistoryTo] = useState('');
+	const [historyError, setHistoryError] = useState('');
+	const [received, setReceived] = useState([]);
+	const [settings, setSettings] = useState(null);
+	const [settingsMessage, setSettingsMessage] = useState('');
+	const [logs, setLogs] = useState([]);
+	const [logLevel, setLogLevel] = useState('INFO');
+	const [migrateTarget, setMigrateTarget] = useState('paste_rs');
+	const [migrateResult, setMigrateResult] = useState('');
+	const [fileChecks, setFileChecks] = useState({});
 
 	const onDrop = useCallback((acceptedFiles) => {
 		const newFiles = acceptedFiles.map((file) => ({
@@ -22,12 +73,183 @@ function App() {
 	const handleUpload = () => {
 		const filePaths = files.map((file) => file.path);
 		console.log(filePaths); // Log file paths to verify
-		invoke('process_files', { filePaths })
+		const options = {
+			compression:
+				compression === 'none'
+					? null
+					: {
+							codec: compression,
+							level: compressionLevel ? Number(compressionLevel) : null,
+					  },
+			encoding: encoding || null,
+			parity: parityShards
+				? { dataShards: 16, parityShards: Number(parityShards) }
+				: null,
+			backends: [
+				'innomi',
+				...(mirrorPasteRs ? ['paste_rs'] : []),
+				...(mirrorDirectory ? [{ local: mirrorDirectory }] : []),
+			],
+			keepAlive,
+		};
+		invoke('process_files', { filePaths, options })
 			.then((response) => {
 				console.log(response);
 				setResponseTitle(response);
+				setUploadError('');
+				refreshHistory();
+			})
+			.catch((error) => {
+				console.error(error);
+				setUploadError(describeError(error));
+			});
+	};
+
+	// Dates from the inputs are local midnight, the range includes the whole "to" day
+	const toSeconds = (date, days = 0) =>
+		date ? Math.floor(new Date(`${date}T00:00`).getTime() / 1000) + days * 86400 : null;
+
+	const refreshHistory = () => {
+		const filtered = historyText || historyBackend || historyFrom || historyTo;
+		const request = filtered
+			? invoke('search_history', {
+					query: {
+						text: historyText || null,
+						backend: historyBackend || null,
+						from: toSeconds(historyFrom),
+						to: toSeconds(historyTo, 1),
+					},
+			  })
+			: invoke('list_history');
+		request
+			.then((entries) => {
+				setHistory(entries);
+				setHistoryError('');
+			})
+			.catch((error) => {
+				console.error(error);
+				setHistoryError(describeError(error));
+			});
+	};
+
+	// The listener below is registered once, it goes through this to use the current filters
+	const refreshHistoryRef = useRef(refreshHistory);
+	refreshHistoryRef.current = refreshHistory;
+
+	const refreshReceived = () => {
+		invoke('list_received')
+			.then(setReceived)
+			.catch((error) => setHistoryError(describeError(error)));
+	};
+
+	useEffect(() => {
+		refreshHistory();
+		refreshReceived();
+		// The background liveness check updates the history every now and then
+		const unlisten = listen('history-changed', () => refreshHistoryRef.current());
+		return () => {
+			unlisten.then((stop) => stop());
+		};
+	}, []);
+
+	useEffect(() => {
+		invoke('get_settings')
+			.then(setSettings)
+			.catch((error) => setSettingsMessage(describeError(error)));
+	}, []);
+
+	const updateSetting = (changes) => setSettings((settings) => ({ ...settings, ...changes }));
+
+	const handleChooseDownloadDir = async () => {
+		const folder = await open({ directory: true });
+		if (folder) {
+			updateSetting({ downloadDir: folder });
+		}
+	};
+
+	const handleSaveSettings = () => {
+		invoke('update_settings', { settings })
+			.then((saved) => {
+				setSettings(saved);
+				setSettingsMessage('Saved, used from the next upload or download on');
+			})
+			.catch((error) => setSettingsMessage(describeError(error)));
+	};
+
+	useEffect(() => {
+		invoke('get_logs').then(setLogs);
+		// Keep the last records, as many as the backend buffers
+		const unlisten = listen('log', (event) => setLogs((logs) => [...logs.slice(-499), event.payload]));
+		return () => {
+			unlisten.then((stop) => stop());
+		};
+	}, []);
+
+	const handleCheckHistory = () => {
+		invoke('check_history_now').catch((error) => setHistoryError(describeError(error)));
+	};
+
+	const statusColors = { live: 'green', expiring: 'orange', expired: 'red', unknown: 'gray' };
+	const expireOptions = ['10m', '1h', '1d', '14d', '-1'];
+	const logLevels = ['ERROR', 'WARN', 'INFO', 'DEBUG', 'TRACE'];
+	const levelColors = { ERROR: 'red', WARN: 'orange', INFO: 'black', DEBUG: 'gray', TRACE: 'gray' };
+
+	const handleVerify = (id) => {
+		invoke('verify_received', { id })
+			.then((checks) => setFileChecks((prevChecks) => ({ ...prevChecks, [id]: checks })))
+			.catch((error) => setHistoryError(describeError(error)));
+	};
+
+	const handleOpenFolder = (id) => {
+		invoke('open_received_folder', { id }).catch((error) => setHistoryError(describeError(error)));
+	};
+
+	const handleForgetReceived = (id) => {
+		invoke('delete_received_entry', { id })
+			.then(refreshReceived)
+			.catch((error) => setHistoryError(describeError(error)));
+	};
+
+	const handleDownloadAgain = (link) => {
+		invoke('rebuild_files', { title: link, destination: destination || null, conflict })
+			.then((reports) => {
+				setRebuildReports(reports);
+				setRebuildError('');
+				refreshReceived();
+			})
+			.catch((error) => {
+				console.error(error);
+				setRebuildError(describeError(error));
+			});
+	};
+
+	const handleCopyLink = (id) => {
+		invoke('copy_share_link', { id }).catch((error) => setHistoryError(describeError(error)));
+	};
+
+	const handleKeepAlive = (id, enabled) => {
+		invoke('set_keep_alive', { id, enabled })
+			.then(refreshHistory)
+			.catch((error) => setHistoryError(describeError(error)));
+	};
+
+	const handleForget = (id) => {
+		invoke('delete_history_entry', { id })
+			.then(refreshHistory)
+			.catch((error) => setHistoryError(describeError(error)));
+	};
+
+	const handleRebuildFromHistory = (id) => {
+		invoke('rebuild_from_history', { id, destination: destination || null, conflict })
+			.then((reports) => {
+				setRebuildReports(reports);
+				setRebuildError('');
+				refreshReceived();
 			})
-			.catch((error) => console.error(error));
+			.catch((error) => {
+				console.error(error);
+				setRebuildError(describeError(error));
+			});
 	};
 
 	const handleSelectFiles = async () => {
@@ -42,12 +264,93 @@ function App() {
 		}
 	};
 
+	const handleSelectFolders = async () => {
+		const selectedFolders = await open({ directory: true, multiple: true });
+		if (Array.isArray(selectedFolders)) {
+			const newFiles = selectedFolders.map((folderPath) => ({
+				path: folderPath,
+				name: folderPath.split('/').pop() + '/', // Uploaded with everything inside
+				size: 0,
+			}));
+			setFiles((prevFiles) => [...prevFiles, ...newFiles]);
+		}
+	};
+
+	const handleInspect = () => {
+		invoke('inspect_share', { title: rebuildTitle, probe: probeParts })
+			.then((info) => {
+				setShareInfo(info);
+				setSelectedIds(info.files.map((file) => file.id));
+				setRebuildError('');
+			})
+			.catch((error) => {
+				console.error(error);
+				setShareInfo(null);
+				setRebuildError(describeError(error));
+			});
+	};
+
+	const toggleSelected = (id) => {
+		setSelectedIds((prevIds) =>
+			prevIds.includes(id) ? prevIds.filter((prevId) => prevId !== id) : [...prevIds, id]
+		);
+	};
+
+	const formatSize = (size) =>
+		size == null ? 'unknown size' : `${(size / 1024).toFixed(1)} KB`;
+
+	const handleMigrate = async () => {
+		let backend = migrateTarget;
+		if (migrateTarget === 'local') {
+			const folder = await open({ directory: true });
+			if (!folder) {
+				return;
+			}
+			backend = { local: folder };
+		}
+		invoke('migrate_share', { title: rebuildTitle, backends: [backend] })
+			.then((link) => {
+				setMigrateResult(`Migrated, the new link is ${link}`);
+				setRebuildError('');
+				refreshHistory();
+			})
+			.catch((error) => {
+				console.error(error);
+				setMigrateResult('');
+				setRebuildError(describeError(error));
+			});
+	};
+
+	const formatExpiry = (info) => {
+		if (info.expiresAt == null) {
+			return info.createdAt == null ? 'expiry unknown' : 'never expires';
+		}
+		const date = new Date(info.expiresAt * 1000).toLocaleString();
+		return info.expired ? `expired ${date}` : `expires ${date}`;
+	};
+
 	const handleRebuild = () => {
-		invoke('rebuild_files', { title: rebuildTitle })
-			.then(() => {
+		// Only send a subset when the share was inspected and some files were unticked
+		const fileIds =
+			shareInfo && shareInfo.link === rebuildTitle && selectedIds.length < shareInfo.files.length
+				? selectedIds
+				: null;
+		invoke('rebuild_files', {
+			title: rebuildTitle,
+			fileIds,
+			destination: destination || null,
+			conflict,
+		})
+			.then((reports) => {
 				console.log(`Rebuilding files for title: ${rebuildTitle}`);
+				setRebuildReports(reports);
+				setRebuildError('');
+				refreshReceived();
 			})
-			.catch((error) => console.error(error));
+			.catch((error) => {
+				console.error(error);
+				setRebuildError(describeError(error));
+			});
 	};
 
 	return (
@@ -64,6 +367,7 @@ function App() {
 				<p>Drag & drop a file here, or click to select a file</p>
 			</div>
 			<button onClick={handleSelectFiles}>Select Files</button>
+			<button onClick={handleSelectFolders}>Select Folders</button>
 			<div>
 				<h2>Selected Files</h2>
 				<ul>
@@ -79,11 +383,80 @@ function App() {
 					))}
 				</ul>
 			</div>
+			<div style={{ marginTop: '20px' }}>
+				<label>
+					Compression{' '}
+					<select
+						value={compression}
+						onChange={(e) => setCompression(e.target.value)}>
+						<option value="none">None</option>
+						<option value="zstd">zstd</option>
+						<option value="deflate">deflate</option>
+					</select>
+				</label>
+				{compression !== 'none' && (
+					<input
+						type="number"
+						value={compressionLevel}
+						onChange={(e) => setCompressionLevel(e.target.value)}
+						placeholder="Level (default)"
+					/>
+				)}
+				<label>
+					{' '}Encoding{' '}
+					<select
+						value={encoding}
+						onChange={(e) => setEncoding(e.target.value)}>
+						<option value="">Backend default</option>
+						<option value="base64">base64</option>
+						<option value="z85">Z85</option>
+						<option value="base32768">base32768</option>
+					</select>
+				</label>
+				<label>
+					{' '}Parity parts per 16{' '}
+					<input
+						type="number"
+						min="0"
+						value={parityShards}
+						onChange={(e) => setParityShards(e.target.value)}
+						placeholder="None"
+					/>
+				</label>
+			</div>
+			<div>
+				<label>
+					<input
+						type="checkbox"
+						checked={mirrorPasteRs}
+						onChange={(e) => setMirrorPasteRs(e.target.checked)}
+					/>
+					Also store on paste.rs
+				</label>
+				<button
+					onClick={async () => {
+						const directory = await open({ directory: true });
+						setMirrorDirectory(directory || '');
+					}}>
+					{mirrorDirectory
+						? `Also storing in ${mirrorDirectory}`
+						: 'Also store in a folder'}
+				</button>
+				<label>
+					<input
+						type="checkbox"
+						checked={keepAlive}
+						onChange={(e) => setKeepAlive(e.target.checked)}
+					/>
+					Keep alive while the app runs
+				</label>
+			</div>
 			<button
 				onClick={handleUpload}
 				style={{ marginTop: '20px', padding: '10px 20px' }}>
 				Upload Files
 			</button>
+			{uploadError && <p style={{ color: 'red' }}>{uploadError}</p>}
 			{responseTitle && (
 				<div>
 					<h2>Response Title</h2>
@@ -98,8 +471,259 @@ function App() {
 					onChange={(e) => setRebuildTitle(e.target.value)}
 					placeholder="Enter response title"
 				/>
-				<button onClick={handleRebuild}>Rebuild Files</button>
+				<button onClick={handleInspect}>Inspect</button>
+				<label>
+					<input
+						type="checkbox"
+						checked={probeParts}
+						onChange={(e) => setProbeParts(e.target.checked)}
+					/>
+					Check that every part still exists
+				</label>
+				{shareInfo && shareInfo.link === rebuildTitle && (
+					<p>
+						{shareInfo.files.length} files, {formatSize(shareInfo.totalSize)},{' '}
+						{shareInfo.encrypted ? 'encrypted' : 'not encrypted'}, {formatExpiry(shareInfo)}
+						{shareInfo.probed &&
+							(shareInfo.missingParts > 0
+								? `, ${shareInfo.missingParts} parts are gone`
+								: ', all parts are available')}
+					</p>
+				)}
+				{shareInfo && shareInfo.link === rebuildTitle && (
+					<ul style={{ listStyle: 'none' }}>
+						{shareInfo.files.map((file) => (
+							<li key={file.id}>
+								<label>
+									<input
+										type="checkbox"
+										checked={selectedIds.includes(file.id)}
+										onChange={() => toggleSelected(file.id)}
+									/>
+									{file.path} - {formatSize(file.size)}, {file.mimeType}, {file.parts} parts
+									{file.missingParts.length > 0 &&
+										` (missing parts ${file.missingParts.join(', ')}, ${
+											file.recoverable ? 'recoverable from parity' : 'can not be rebuilt'
+										})`}
+								</label>
+							</li>
+						))}
+					</ul>
+				)}
+				<button
+					onClick={async () => {
+						const folder = await open({ directory: true });
+						setDestination(folder || '');
+					}}>
+					{`Save to ${destination || settings?.downloadDir || 'Downloads'}`}
+				</button>
+				<select
+					value={conflict}
+					onChange={(e) => setConflict(e.target.value)}>
+					<option value="rename">Keep both when a file exists</option>
+					<option value="skip">Skip existing files</option>
+					<option value="overwrite">Overwrite existing files</option>
+					<option value="ask">Ask for each existing file</option>
+				</select>
+				<button
+					onClick={handleRebuild}
+					disabled={shareInfo && shareInfo.link === rebuildTitle && selectedIds.length === 0}>
+					Rebuild Files
+				</button>
+				<select value={migrateTarget} onChange={(e) => setMigrateTarget(e.target.value)}>
+					<option value="paste_rs">paste.rs</option>
+					<option value="innomi">innomi</option>
+					<option value="local">A folder</option>
+				</select>
+				<button onClick={handleMigrate}>Migrate</button>
+				{migrateResult && <p>{migrateResult}</p>}
+				{rebuildError && <p style={{ color: 'red' }}>{rebuildError}</p>}
+				<ul>
+					{rebuildReports.map((report) => (
+						<li key={report.id}>
+							{report.path} -{' '}
+							{report.savedTo
+								? `${report.parts} parts, saved to ${report.savedTo}`
+								: 'skipped, it already exists'}
+							{report.lostParts > 0 &&
+								`, recovered ${report.lostParts} lost parts from ${report.parityParts} parity parts (worst stripe lost ${report.worstStripeLost})`}
+						</li>
+					))}
+				</ul>
 			</div>
+			<div style={{ marginTop: '20px' }}>
+				<h2>Sent Files</h2>
+				<input
+					type="text"
+					value={historyText}
+					onChange={(e) => setHistoryText(e.target.value)}
+					placeholder="File name or link"
+				/>
+				<select value={historyBackend} onChange={(e) => setHistoryBackend(e.target.value)}>
+					<option value="">Any backend</option>
+					<option value="innomi">innomi</option>
+					<option value="paste_rs">paste.rs</option>
+				</select>
+				<input type="date" value={historyFrom} onChange={(e) => setHistoryFrom(e.target.value)} />
+				<input type="date" value={historyTo} onChange={(e) => setHistoryTo(e.target.value)} />
+				<button onClick={refreshHistory}>Search</button>
+				<button onClick={handleCheckHistory}>Check now</button>
+				{historyError && <p style={{ color: 'red' }}>{historyError}</p>}
+				<ul>
+					{history.map((entry) => (
+						<li key={entry.id}>
+							{entry.createdAt ? new Date(entry.createdAt * 1000).toLocaleString() : 'Unknown date'}{' '}
+							- {entry.files.map((file) => file.path).join(', ')} ({entry.pointer || entry.link}){' '}
+							<span style={{ color: statusColors[entry.status] }}>
+								{entry.status}
+								{entry.expiresAt &&
+									entry.status !== 'expired' &&
+									`, expires ${new Date(entry.expiresAt * 1000).toLocaleString()}`}
+							</span>
+							<button onClick={() => handleCopyLink(entry.id)}>Copy link</button>
+							<button onClick={() => handleRebuildFromHistory(entry.id)}>Rebuild</button>
+							<button onClick={() => handleForget(entry.id)}>Forget</button>
+							<label>
+								<input
+									type="checkbox"
+									checked={entry.keepAlive}
+									onChange={(e) => handleKeepAlive(entry.id, e.target.checked)}
+								/>
+								Keep alive
+								{entry.keepAlive &&
+									!entry.pointer &&
+									' (innomi and paste.rs pastes can\'t be changed, so the link changes on every re-upload. Mirror to a folder for a link that stays the same.)'}
+							</label>
+						</li>
+					))}
+				</ul>
+			</div>
+			<div style={{ marginTop: '20px' }}>
+				<h2>Received Files</h2>
+				<ul>
+					{received.map((entry) => (
+						<li key={entry.id}>
+							{new Date(entry.receivedAt * 1000).toLocaleString()} -{' '}
+							{entry.files.map((file) => file.path).join(', ')} ({entry.link}) in {entry.destination}
+							<button onClick={() => handleOpenFolder(entry.id)}>Open folder</button>
+							<button onClick={() => handleVerify(entry.id)}>Check files</button>
+							<button onClick={() => handleDownloadAgain(entry.link)}>Download again</button>
+							<button onClick={() => handleForgetReceived(entry.id)}>Forget</button>
+							{fileChecks[entry.id] && (
+								<ul>
+									{fileChecks[entry.id].map((check) => (
+										<li key={check.savedTo}>
+											{check.savedTo}: {check.state}
+										</li>
+									))}
+								</ul>
+							)}
+						</li>
+					))}
+				</ul>
+			</div>
+			<details style={{ marginTop: '20px' }}>
+				<summary>Settings</summary>
+				{settings && (
+					<div>
+						<label>
+							Parts expire after{' '}
+							<select value={settings.partExpire} onChange={(e) => updateSetting({ partExpire: e.target.value })}>
+								{expireOptions.map((expire) => (
+									<option key={expire} value={expire}>
+										{expire}
+									</option>
+								))}
+							</select>
+						</label>
+						<label>
+							Manifests expire after{' '}
+							<select
+								value={settings.manifestExpire}
+								onChange={(e) => updateSetting({ manifestExpire: e.target.value })}>
+								{expireOptions.map((expire) => (
+									<option key={expire} value={expire}>
+										{expire}
+									</option>
+								))}
+							</select>
+						</label>
+						<label>
+							Largest part (characters, empty for as large as the host takes){' '}
+							<input
+								type="number"
+								min="1024"
+								value={settings.maxChunkSize ?? ''}
+								onChange={(e) => updateSetting({ maxChunkSize: e.target.value ? Number(e.target.value) : null })}
+							/>
+						</label>
+						<label>
+							Parallel transfers per file{' '}
+							<input
+								type="number"
+								min="1"
+								max="64"
+								value={settings.maxParallelTransfers}
+								onChange={(e) => updateSetting({ maxParallelTransfers: Number(e.target.value) })}
+							/>
+						</label>
+						<div>
+							Save received files in {settings.downloadDir || 'the Downloads folder'}{' '}
+							<button onClick={handleChooseDownloadDir}>Choose</button>
+							{settings.downloadDir && (
+								<button onClick={() => updateSetting({ downloadDir: null })}>Use Downloads</button>
+							)}
+						</div>
+						<label>
+							innomi address{' '}
+							<input
+								type="text"
+								value={settings.endpoints.innomi}
+								onChange={(e) => updateSetting({ endpoints: { ...settings.endpoints, innomi: e.target.value } })}
+							/>
+						</label>
+						<label>
+							paste.rs address{' '}
+							<input
+								type="text"
+								value={settings.endpoints.pasteRs}
+								onChange={(e) => updateSetting({ endpoints: { ...settings.endpoints, pasteRs: e.target.value } })}
+							/>
+						</label>
+						<label>
+							<input
+								type="checkbox"
+								checked={settings.dropExpired}
+								onChange={(e) => updateSetting({ dropExpired: e.target.checked })}
+							/>
+							Remove expired shares from the history automatically
+						</label>
+						<button onClick={handleSaveSettings}>Save settings</button>
+					</div>
+				)}
+				{settingsMessage && <p>{settingsMessage}</p>}
+			</details>
+			<details style={{ marginTop: '20px' }}>
+				<summary>Log</summary>
+				<select value={logLevel} onChange={(e) => setLogLevel(e.target.value)}>
+					{logLevels.map((level) => (
+						<option key={level} value={level}>
+							{level} and above
+						</option>
+					))}
+				</select>
+				<button onClick={() => invoke('open_log_folder').catch((error) => console.error(error))}>Open log folder</button>
+				<pre style={{ maxHeight: '300px', overflow: 'auto', fontSize: '12px' }}>
+					{logs
+						.filter((record) => logLevels.indexOf(record.level) <= logLevels.indexOf(logLevel))
+						.map((record, index) => (
+							<div key={index} style={{ color: levelColors[record.level] }}>
+								{new Date(record.at).toLocaleTimeString()} {record.level} {record.spans && `${record.spans}: `}
+								{record.message}
+							</div>
+						))}
+				</pre>
+			</details>
 		</div>
 	);
 }
@@ -0,0 +1,117 @@
+use serde::Serialize;
+use quickfile_core::error::Error;
+
+// What went wrong, each kind exits with its own code so scripts can tell
+// failures apart
+#[derive(Serialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub enum ErrorKind {
+    Other,
+    // Bad arguments, clap exits with the same code for its own errors
+    Usage,
+    // A backend couldn't be reached or refused the request
+    Network,
+    // Parts were fetched but don't decode into the file the manifest describes
+    Integrity,
+    // The share, or the parts it needs, are gone from the host
+    Expired,
+    // The share looks crafted, e.g. it writes outside the destination
+    Refused,
+    // A bug in quickfile rather than a problem with the input
+    Internal,
+}
+
+impl ErrorKind {
+    pub fn exit_code(self) -> u8 {
+        match self {
+            ErrorKind::Other => 1,
+            ErrorKind::Usage => 2,
+            ErrorKind::Network => 3,
+            ErrorKind::Integrity => 4,
+            // 5 is kept for a wrong key, shares aren't encrypted yet
+            ErrorKind::Expired => 6,
+            ErrorKind::Refused => 7,
+            ErrorKind::Internal => 8,
+        }
+    }
+
+    // Kinds of the core errors, read from their code
+    fn of(error: &Error) -> Self {
+        match error {
+            Error::Expired(_) => ErrorKind::Expired,
+            Error::Integrity { .. } | Error::Decode(_) => ErrorKind::Integrity,
+            Error::Http { .. } | Error::ParseHtml(_) => ErrorKind::Network,
+            Error::Invalid(_) => ErrorKind::Usage,
+            Error::Refused(_) => ErrorKind::Refused,
+            Error::Internal(_) => ErrorKind::Internal,
+            // Only generating random ids uses the crypto library so far
+            Error::Io(_) | Error::Crypto(_) | Error::Cancelled => ErrorKind::Other,
+        }
+    }
+}
+
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct Failure {
+    pub kind: ErrorKind,
+    // The core error code, when the failure came from the core
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub code: Option<&'static str>,
+    pub message: String,
+}
+
+impl Failure {
+    pub fn usage(message: impl Into<String>) -> Self {
+        Failure { kind: ErrorKind::Usage, code: None, message: message.into() }
+    }
+}
+
+impl From<Error> for Failure {
+    fn from(error: Error) -> Self {
+        Failure { kind: ErrorKind::of(&error), code: Some(error.code()), message: error.to_string() }
+    }
+}
+
+impl From<String> for Failure {
+    fn from(message: String) -> Self {
+        Failure { kind: ErrorKind::Other, code: None, message }
+    }
+}
+
+impl From<&str> for Failure {
+    fn from(message: &str) -> Self {
+        message.to_string().into()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn exit_code(error: Error) -> u8 {
+        Failure::from(error).kind.exit_code()
+    }
+
+    #[test]
+    fn every_kind_has_its_exit_code() {
+        assert_eq!(exit_code(Error::Io("Failed to read a.txt".to_string())), 1);
+        assert_eq!(exit_code(Error::Crypto("No random bytes".to_string())), 1);
+        assert_eq!(exit_code(Error::Cancelled), 1);
+        assert_eq!(exit_code(Error::invalid("The share has no file with id 9")), 2);
+        assert_eq!(Failure::usage("Pick at most one --file").kind.exit_code(), 2);
+        assert_eq!(exit_code(Error::Http { status: None, message: "Connection refused".to_string() }), 3);
+        assert_eq!(exit_code(Error::Http { status: Some(502), message: "Bad gateway".to_string() }), 3);
+        assert_eq!(exit_code(Error::ParseHtml("The page has no div#code".to_string())), 3);
+        assert_eq!(exit_code(Error::integrity("a.txt", Some(2), "Invalid base64")), 4);
+        assert_eq!(exit_code(Error::Decode("Failed to parse manifest".to_string())), 4);
+        assert_eq!(exit_code(Error::Expired("The share has probably expired".to_string())), 6);
+        assert_eq!(exit_code(Error::refused("Refusing to rebuild \"../a\"")), 7);
+        assert_eq!(exit_code(Error::Internal("A part task stopped unexpectedly".to_string())), 8);
+    }
+
+    #[test]
+    fn failures_keep_the_core_error_code() {
+        let failure = Failure::from(Error::Expired("Gone".to_string()));
+        assert_eq!(serde_json::to_value(&failure).unwrap(), serde_json::json!({ "kind": "expired", "code": "expired", "message": "Gone" }));
+    }
+}
@@ -0,0 +1,332 @@
+use std::io::{self, BufRead, Write};
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+use clap::{Args, Parser, Subcommand};
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+use tokio::runtime::Runtime;
+
+use quickfile_core::backend::{Backend, unix_now};
+use quickfile_core::compression::{Codec, CompressionOptions};
+use quickfile_core::encoding::Encoding;
+use quickfile_core::error::Error;
+use quickfile_core::history::{self, HistoryQuery, ReceivedEntry};
+use quickfile_core::inspect::{self, ShareInfo};
+use quickfile_core::logging::{self, LevelFilter};
+use quickfile_core::parity::ParityOptions;
+use quickfile_core::settings;
+use quickfile_core::share::{self, UploadOptions};
+use quickfile_core::tree::ConflictPolicy;
+
+mod failure;
+
+use failure::Failure;
+
+// Sends and receives shares without the desktop app. Links work in both.
+#[derive(Parser)]
+#[command(name = "quickfile", version, after_help = EXIT_CODES)]
+struct Cli {
+    #[command(subcommand)]
+    command: Command,
+    /// Print results and errors as JSON
+    #[arg(long, global = true)]
+    json: bool,
+    /// Print progress to stderr, not just warnings. QUICKFILE_LOG=debug shows every request
+    #[arg(short, long, global = true)]
+    verbose: bool,
+}
+
+const EXIT_CODES: &str = "Exit codes: 0 success, 1 other error, 2 usage, 3 network, 4 integrity, 6 expired share, 7 refused a crafted share, 8 internal error";
+
+// `send --json` output, the share as `inspect` shows it plus the manifest mirrors
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct SendOutput {
+    #[serde(flatten)]
+    share: ShareInfo,
+    mirrors: Vec<String>,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Upload files and directories, or stdin with `-`, and print the share link
+    Send {
+        #[arg(required = true)]
+        paths: Vec<String>,
+        /// Name to record for a stream read from stdin
+        #[arg(long)]
+        name: Option<String>,
+        #[command(flatten)]
+        upload: UploadArgs,
+    },
+    /// Download a share and rebuild its files, or write its one file to stdout with `-`
+    Receive {
+        link: String,
+        #[arg(value_parser = ["-"])]
+        stdout: Option<String>,
+        /// Directory to save the files in, the download folder from the settings by default
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+        /// Only rebuild these file ids, see `inspect`
+        #[arg(long = "file")]
+        files: Vec<String>,
+        /// What to do with existing files: rename, skip, overwrite or ask
+        #[arg(long, default_value = "rename")]
+        conflict: String,
+    },
+    /// Show the files of a share and when it expires
+    Inspect {
+        link: String,
+        /// Also check that every part can still be downloaded
+        #[arg(long)]
+        probe: bool,
+    },
+    /// List sent shares, or received ones with --received
+    History {
+        /// Only entries whose link or file paths contain this text
+        text: Option<String>,
+        #[arg(long)]
+        received: bool,
+    },
+}
+
+// How `send` stores the share
+#[derive(Args)]
+struct UploadArgs {
+    /// Where to store the share: innomi, paste_rs or a directory. Repeat to mirror
+    #[arg(short, long = "backend")]
+    backends: Vec<String>,
+    /// Chunk encoding: base64, z85 or base32768
+    #[arg(long)]
+    encoding: Option<String>,
+    /// Compression codec: none, zstd or deflate
+    #[arg(long)]
+    compression: Option<String>,
+    /// Compression level for the codec
+    #[arg(long, requires = "compression")]
+    level: Option<i32>,
+    /// Reed-Solomon parity as DATA:PARITY parts per stripe, e.g. 10:2
+    #[arg(long)]
+    parity: Option<String>,
+}
+
+impl UploadArgs {
+    fn options(self) -> Result<UploadOptions, Failure> {
+        let level = self.level;
+        Ok(UploadOptions {
+            compression: self.compression.map(|codec| -> Result<_, Failure> {
+                Ok(CompressionOptions { codec: parse_value::<Codec>(&codec, "compression")?, level })
+            }).transpose()?,
+            encoding: self.encoding.map(|encoding| parse_value::<Encoding>(&encoding, "encoding")).transpose()?,
+            backends: self.backends.iter().map(|backend| parse_backend(backend)).collect(),
+            parity: self.parity.map(|parity| parse_parity(&parity)).transpose()?,
+            // Only the app runs long enough to refresh shares
+            keep_alive: false,
+        })
+    }
+}
+
+// Parses a value the way the app sends it, e.g. "zstd" or "rename"
+fn parse_value<T: DeserializeOwned>(value: &str, what: &str) -> Result<T, Failure> {
+    serde_json::from_value(serde_json::Value::String(value.to_lowercase()))
+        .map_err(|_| Failure::usage(format!("Unknown {} \"{}\"", what, value)))
+}
+
+fn parse_backend(value: &str) -> Backend {
+    match value {
+        "innomi" => Backend::Innomi,
+        "paste_rs" | "paste.rs" => Backend::PasteRs,
+        path => Backend::Local(PathBuf::from(path)),
+    }
+}
+
+fn parse_parity(value: &str) -> Result<ParityOptions, Failure> {
+    let invalid = || Failure::usage(format!("Parity \"{}\" should look like DATA:PARITY, e.g. 10:2", value));
+    let (data, parity) = value.split_once(':').ok_or_else(invalid)?;
+    Ok(ParityOptions {
+        data_shards: data.parse().map_err(|_| invalid())?,
+        parity_shards: parity.parse().map_err(|_| invalid())?,
+    })
+}
+
+// Asks on the terminal whether an existing file should be replaced
+fn ask_overwrite(path: &Path) -> bool {
+    eprint!("{} already exists. Replace it? [y/N] ", path.display());
+    let _ = io::stderr().flush();
+    let mut answer = String::new();
+    io::stdin().lock().read_line(&mut answer).is_ok() && answer.trim().eq_ignore_ascii_case("y")
+}
+
+fn format_size(size: u64) -> String {
+    const UNITS: [&str; 4] = ["B", "KB", "MB", "GB"];
+    let mut size = size as f64;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+    format!("{:.1} {}", size, UNITS[unit])
+}
+
+fn print_json<T: Serialize>(value: &T) -> Result<(), Failure> {
+    println!("{}", serde_json::to_string_pretty(value).map_err(Error::from)?);
+    Ok(())
+}
+
+// "in 2h" or "3d ago", relative to now
+fn format_time(at: u64) -> String {
+    let now = unix_now();
+    let seconds = at.abs_diff(now);
+    let span = match seconds {
+        0..=59 => format!("{}s", seconds),
+        60..=3599 => format!("{}m", seconds / 60),
+        3600..=86399 => format!("{}h", seconds / 3600),
+        _ => format!("{}d", seconds / 86400),
+    };
+    if at > now { format!("in {}", span) } else { format!("{} ago", span) }
+}
+
+async fn send(json: bool, paths: Vec<String>, name: Option<String>, upload: UploadArgs) -> Result<(), Failure> {
+    let options = upload.options()?;
+    let sent = if paths.iter().any(|path| path == "-") {
+        if paths.len() > 1 {
+            return Err(Failure::usage("`-` reads stdin and can't be combined with other paths"));
+        }
+        share::send_stream(tokio::io::stdin(), name, &options).await?
+    } else {
+        if name.is_some() {
+            return Err(Failure::usage("--name only applies to a stream sent with `-`"));
+        }
+        share::send(&paths, &options).await?
+    };
+    if json {
+        return print_json(&SendOutput { share: inspect::summarize(sent.link, &sent.manifest), mirrors: sent.mirrors });
+    }
+    for file in &sent.manifest.files {
+        eprintln!("{}  {}", file.path, file.size.map(format_size).unwrap_or_default());
+    }
+    if let Some(expires_at) = sent.manifest.expires_at {
+        eprintln!("Expires {}", format_time(expires_at));
+    }
+    println!("{}", sent.link);
+    Ok(())
+}
+
+async fn receive(json: bool, link: String, stdout: bool, output: Option<PathBuf>, files: Vec<String>, conflict: String) -> Result<(), Failure> {
+    if stdout {
+        if output.is_some() || files.len() > 1 {
+            return Err(Failure::usage("`-` writes a single file to stdout, pick at most one --file and no --output"));
+        }
+        let report = share::receive_stream(&link, files.first().map(String::as_str), &mut tokio::io::stdout()).await?;
+        // stdout carries the file, so the report goes to stderr
+        match json {
+            true => eprintln!("{}", serde_json::to_string_pretty(&report).map_err(Error::from)?),
+            false => eprintln!("Wrote {} ({}) to stdout", report.path, format_size(report.size.unwrap_or_default())),
+        }
+        return Ok(());
+    }
+    let destination = match output {
+        Some(output) => output,
+        None => settings::current().download_dir.or_else(dirs::download_dir).ok_or("No Downloads folder found, choose one with --output")?,
+    };
+    let conflict: ConflictPolicy = parse_value(&conflict, "conflict policy")?;
+    let file_ids = if files.is_empty() { None } else { Some(files) };
+    let reports = share::receive(vec![link], file_ids, destination, conflict, &ask_overwrite).await?;
+    if json {
+        return print_json(&reports);
+    }
+    for report in reports {
+        match report.saved_to {
+            Some(saved_to) => println!("{} -> {}", report.path, saved_to.display()),
+            None => println!("{} skipped, it already exists", report.path),
+        }
+        if report.recovery.lost_parts > 0 {
+            eprintln!("  recovered {} lost parts from parity", report.recovery.lost_parts);
+        }
+    }
+    Ok(())
+}
+
+async fn inspect(json: bool, link: String, probe: bool) -> Result<(), Failure> {
+    let info = inspect::inspect(&link, probe).await?;
+    if json {
+        return print_json(&info);
+    }
+    println!("{}: {} files, {}", info.link, info.files.len(), format_size(info.total_size));
+    if let Some(stream) = &info.stream {
+        println!("Sent from a stream{}", stream.name.as_ref().map(|name| format!(" named {}", name)).unwrap_or_default());
+    }
+    match info.expires_at {
+        Some(_) if info.expired => println!("Expired"),
+        Some(expires_at) => println!("Expires {}", format_time(expires_at)),
+        None => println!("Expiry unknown"),
+    }
+    for file in &info.files {
+        let size = file.size.map(format_size).unwrap_or_else(|| "?".to_string());
+        println!("{}  {}  {}  {} parts  {}", file.id, file.path, size, file.parts, file.mime_type);
+        if !file.missing_parts.is_empty() {
+            let state = if file.recoverable { "recoverable" } else { "lost" };
+            println!("  missing parts {:?}, {}", file.missing_parts, state);
+        }
+    }
+    for directory in &info.directories {
+        println!("{}/", directory);
+    }
+    Ok(())
+}
+
+fn history(json: bool, text: Option<String>, received: bool) -> Result<(), Failure> {
+    if received {
+        let text = text.map(|text| text.to_lowercase());
+        let matches = |value: &str| text.as_ref().is_none_or(|text| value.to_lowercase().contains(text));
+        let entries: Vec<ReceivedEntry> = history::load::<ReceivedEntry>()?.into_iter()
+            .filter(|entry| matches(&entry.link) || entry.files.iter().any(|file| matches(&file.path)))
+            .collect();
+        if json {
+            return print_json(&entries);
+        }
+        for entry in entries {
+            println!("{}  {}  {} files  {}  {}", entry.id, entry.link, entry.files.len(), entry.destination.display(), format_time(entry.received_at));
+        }
+        return Ok(());
+    }
+    let entries = history::search(&HistoryQuery { text, ..Default::default() })?;
+    if json {
+        return print_json(&entries);
+    }
+    for entry in entries {
+        let created = entry.created_at.map(format_time).unwrap_or_default();
+        let status = serde_json::to_value(entry.status).ok().and_then(|status| status.as_str().map(str::to_string)).unwrap_or_default();
+        println!("{}  {}  {} files  {}  {}", entry.id, entry.share_link(), entry.files.len(), status, created);
+    }
+    Ok(())
+}
+
+fn main() -> ExitCode {
+    let cli = Cli::parse();
+    let json = cli.json;
+    // `receive <link> -` writes the file to stdout, so nothing else may go there
+    let streaming = matches!(cli.command, Command::Receive { stdout: Some(_), .. });
+    let _log = logging::init(if cli.verbose { LevelFilter::DEBUG } else { LevelFilter::WARN });
+    let result = Runtime::new().map_err(|e| Failure::from(Error::io("Failed to start the runtime", e))).and_then(|rt| match cli.command {
+        Command::Send { paths, name, upload } => rt.block_on(send(json, paths, name, upload)),
+        Command::Receive { link, stdout, output, files, conflict } => rt.block_on(receive(json, link, stdout.is_some(), output, files, conflict)),
+        Command::Inspect { link, probe } => rt.block_on(inspect(json, link, probe)),
+        Command::History { text, received } => history(json, text, received),
+    });
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(failure) => {
+            if json {
+                let error = serde_json::to_string_pretty(&serde_json::json!({ "error": failure })).unwrap_or_default();
+                match streaming {
+                    true => eprintln!("{}", error),
+                    false => println!("{}", error),
+                }
+            } else {
+                eprintln!("Error: {}", failure.message);
+            }
+            ExitCode::from(failure.kind.exit_code())
+        }
+    }
+}
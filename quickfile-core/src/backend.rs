@@ -0,0 +1,520 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, OnceLock, PoisonError};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use serde::{Serialize, Deserialize};
+use reqwest::Client;
+use tracing::{debug, info, warn};
+use crate::encoding::Encoding;
+use crate::error::Error;
+use crate::history::history_dir;
+use crate::html;
+use crate::settings;
+use crate::tree;
+
+#[derive(Serialize, Deserialize)]
+struct PartData {
+    lang: String,
+    text: String,
+    expire: String,
+    password: String,
+    title: String,
+}
+
+// Places parts and manifests can be stored
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq, Eq, Hash)]
+#[serde(rename_all = "snake_case")]
+pub enum Backend {
+    #[default]
+    Innomi,
+    // paste.rs keeps pastes until they're deleted and ignores the expiry
+    PasteRs,
+    // A directory, e.g. a synced or network drive
+    Local(PathBuf),
+}
+
+// Where one copy of a part or manifest lives
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct Location {
+    pub backend: Backend,
+    pub id: String,
+}
+
+// Largest paste a backend accepts
+pub enum PasteLimit {
+    // Documented by the host, in bytes of UTF-8 text
+    Fixed(usize),
+    // Unknown, found by uploading test pastes between the two sizes, in
+    // characters of encoded text
+    Probe { known_good: usize, upper_bound: usize },
+}
+
+// Number of bisection steps when the upper bound of a probe is rejected
+const PROBE_STEPS: usize = 4;
+// Probed limits are kept in the app data folder and probed again after this
+// long, in case the host changed its limit
+const PROBED_LIMITS_FILE: &str = "paste_limits.json";
+const PROBE_MAX_AGE: u64 = 30 * 24 * 60 * 60;
+
+// Result of probing one host for one encoding
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+struct ProbedLimit {
+    backend: Backend,
+    endpoint: String,
+    encoding: Encoding,
+    size: usize,
+    probed_at: u64,
+}
+
+// Expire values innomi offers, "-1" keeps a paste forever
+pub const EXPIRE_VALUES: [&str; 5] = ["10m", "1h", "1d", "14d", "-1"];
+
+// Lifetime of an innomi expire value, None for "-1"
+pub fn expire_duration(expire: &str) -> Result<Option<Duration>, Error> {
+    if expire == "-1" {
+        return Ok(None);
+    }
+    let invalid = || Error::invalid(format!("Invalid expire value {:?}", expire));
+    let unit = expire.chars().last().ok_or_else(invalid)?;
+    let count: u64 = expire[..expire.len() - unit.len_utf8()].parse().map_err(|_| invalid())?;
+    let seconds = match unit {
+        'm' => 60,
+        'h' => 60 * 60,
+        'd' => 24 * 60 * 60,
+        _ => return Err(invalid()),
+    };
+    Ok(Some(Duration::from_secs(count.checked_mul(seconds).ok_or_else(invalid)?)))
+}
+
+// Seconds since the Unix epoch
+pub fn unix_now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0)
+}
+
+// Probed limits, read from the app data folder on first use
+fn probed_limits() -> &'static Mutex<Vec<ProbedLimit>> {
+    static LIMITS: OnceLock<Mutex<Vec<ProbedLimit>>> = OnceLock::new();
+    LIMITS.get_or_init(|| Mutex::new(load_probed_limits()))
+}
+
+// Limits probed earlier, none if they can't be read
+fn load_probed_limits() -> Vec<ProbedLimit> {
+    let Ok(path) = history_dir().map(|dir| dir.join(PROBED_LIMITS_FILE)) else {
+        return vec![];
+    };
+    let Ok(json) = fs::read_to_string(&path) else {
+        return vec![];
+    };
+    serde_json::from_str(&json).unwrap_or_else(|e| {
+        warn!("Probing paste limits again, {} can't be read: {}", path.display(), e);
+        vec![]
+    })
+}
+
+fn save_probed_limits(limits: &[ProbedLimit]) -> Result<(), Error> {
+    let dir = history_dir()?;
+    fs::create_dir_all(&dir).map_err(|e| Error::io(format!("Failed to create {}", dir.display()), e))?;
+    tree::write_atomically(&dir, &dir.join(PROBED_LIMITS_FILE), serde_json::to_string_pretty(limits)?.as_bytes())
+}
+
+// Whether a backend's raw endpoint answered with plain text, once known
+fn raw_support() -> &'static Mutex<HashMap<Backend, bool>> {
+    static RAW: OnceLock<Mutex<HashMap<Backend, bool>>> = OnceLock::new();
+    RAW.get_or_init(|| Mutex::new(HashMap::new()))
+}
+
+// The id in the address of an innomi paste page, /paste/<id>
+fn innomi_id(url: &reqwest::Url) -> Option<String> {
+    let mut segments = url.path_segments()?;
+    match (segments.next(), segments.next(), segments.next()) {
+        (Some("paste"), Some(id), None) if !id.is_empty() && id != "new" => Some(id.to_string()),
+        _ => None,
+    }
+}
+
+impl Backend {
+    // Address of the paste host from the settings, None for a directory
+    fn endpoint(&self) -> Option<String> {
+        let endpoints = settings::current().endpoints;
+        let endpoint = match self {
+            Backend::Innomi => endpoints.innomi,
+            Backend::PasteRs => endpoints.paste_rs,
+            Backend::Local(_) => return None,
+        };
+        Some(endpoint.trim_end_matches('/').to_string())
+    }
+
+    pub fn paste_url(&self, id: &str) -> String {
+        match (self, self.endpoint()) {
+            (Backend::Innomi, Some(endpoint)) => format!("{}/paste/{}", endpoint, id),
+            (Backend::Local(dir), _) => dir.join(id).display().to_string(),
+            (_, endpoint) => format!("{}/{}", endpoint.unwrap_or_default(), id),
+        }
+    }
+
+    // The paste as plain text, without the page around it
+    pub fn raw_url(&self, id: &str) -> Option<String> {
+        match self {
+            Backend::Innomi => Some(format!("{}/raw", self.paste_url(id))),
+            Backend::PasteRs | Backend::Local(_) => None,
+        }
+    }
+
+    pub fn paste_limit(&self) -> PasteLimit {
+        match self {
+            // 1MB parts have always worked, the performance harness uploads
+            // 5MB parts, nothing documents the actual cap
+            Backend::Innomi => PasteLimit::Probe { known_good: 1024 * 1024, upper_bound: 8 * 1024 * 1024 },
+            // Larger pastes are cut off with a 206. Not probed, test pastes
+            // would stay forever since paste.rs ignores the expiry.
+            Backend::PasteRs => PasteLimit::Fixed(128 * 1024),
+            Backend::Local(_) => PasteLimit::Fixed(16 * 1024 * 1024),
+        }
+    }
+
+    // Whether text stored with `encoding` comes back unchanged
+    pub fn preserves(&self, encoding: Encoding) -> bool {
+        match (self, encoding) {
+            // Pastes are stored as UTF-8 and shown HTML-escaped, which
+            // parsing the page undoes, so any encoding survives
+            (Backend::Innomi, _) => true,
+            // paste.rs limits pastes by bytes rather than characters, and
+            // Base32768 characters take 3 bytes each
+            (Backend::PasteRs, Encoding::Base32768) => false,
+            (Backend::PasteRs, Encoding::Base64 | Encoding::Z85) => true,
+            // Raw UTF-8 in, raw UTF-8 out
+            (Backend::Local(_), _) => true,
+        }
+    }
+
+    // Whether pastes are deleted after their expire value
+    pub fn expires(&self) -> bool {
+        matches!(self, Backend::Innomi)
+    }
+
+    // Whether a stored paste can be replaced under the same id
+    pub fn rewritable(&self) -> bool {
+        matches!(self, Backend::Local(_))
+    }
+
+    // Encoding that needs the fewest request bytes on this host. Parts are
+    // posted form-urlencoded, which escapes most of Z85's punctuation and
+    // every Base32768 character, so on innomi base64 ends up smallest
+    // (see `cargo run -- encodings` in testing/Performance).
+    pub fn preferred_encoding(&self) -> Encoding {
+        match self {
+            Backend::Innomi => Encoding::Base64,
+            // Bodies are sent as is, so the fewest UTF-8 bytes wins
+            Backend::PasteRs | Backend::Local(_) => Encoding::Z85,
+        }
+    }
+
+    // Stores `text` and returns the id it can be fetched with
+    pub async fn post_paste(&self, client: &Client, text: String, expire: &str) -> Result<String, Error> {
+        match self {
+            Backend::Innomi => {
+                let url = self.paste_url("new");
+                let data = PartData {
+                    lang: "text".to_string(),
+                    text,
+                    expire: expire.to_string(),
+                    password: "".to_string(),
+                    title: "".to_string(),
+                };
+                let res = client.post(&url)
+                    .header("Content-Type", "application/x-www-form-urlencoded")
+                    .body(serde_urlencoded::to_string(&data).map_err(|e| Error::invalid(e.to_string()))?)
+                    .send()
+                    .await?;
+
+                debug!("Posted {} characters to {}: {}", data.text.len(), url, res.status());
+                if !res.status().is_success() {
+                    return Err(Error::status(res.status(), "Upload failed"));
+                }
+                // Posting redirects to the new paste, so its address has the
+                // id. The title of the page has it too.
+                if let Some(id) = innomi_id(res.url()) {
+                    return Ok(id);
+                }
+                let body = res.text().await?;
+                html::paste_title(&body).map_err(|e| e.context(&url))
+            }
+            Backend::PasteRs => {
+                let url = self.paste_url("");
+                let length = text.len();
+                let res = client.post(&url).body(text).send().await?;
+                debug!("Posted {} characters to {}: {}", length, url, res.status());
+                // 206 means the paste was cut off at the size limit
+                if res.status() != reqwest::StatusCode::CREATED {
+                    return Err(Error::status(res.status(), "Upload failed"));
+                }
+                let body = res.text().await?;
+                body.trim().rsplit('/').next()
+                    .filter(|id| !id.is_empty())
+                    .map(|id| id.to_string())
+                    .ok_or_else(|| Error::ParseHtml(format!("Failed to find the paste id in the response from {}", url)))
+            }
+            Backend::Local(dir) => {
+                let mut random = [0u8; 12];
+                openssl::rand::rand_bytes(&mut random)?;
+                let id = format!("{}.txt", hex::encode(random));
+                fs::create_dir_all(dir).map_err(|e| Error::io(format!("Failed to create {}", dir.display()), e))?;
+                fs::write(dir.join(&id), text).map_err(|e| Error::io(format!("Failed to write {}", dir.join(&id).display()), e))?;
+                Ok(id)
+            }
+        }
+    }
+
+    // Replaces the text stored under `id`, see `rewritable`
+    pub fn rewrite_paste(&self, id: &str, text: &str) -> Result<(), Error> {
+        match self {
+            Backend::Local(dir) => fs::write(dir.join(id), text).map_err(|e| Error::io(format!("Failed to rewrite {}", id), e)),
+            _ => Err(Error::invalid(format!("{:?} can't replace pastes", self))),
+        }
+    }
+
+    // Fetches the text stored under `id`
+    pub async fn fetch_paste(&self, client: &Client, id: &str) -> Result<String, Error> {
+        let url = self.paste_url(id);
+        match self {
+            Backend::Innomi => {
+                if let Some(text) = self.fetch_raw(client, id).await? {
+                    return Ok(text);
+                }
+                let res = client.get(&url).send().await?;
+                if !res.status().is_success() {
+                    return Err(Error::status(res.status(), format!("Failed to download {}", url)));
+                }
+                let body = res.text().await?;
+                // The page is there while the raw endpoint wasn't, so don't ask it again
+                raw_support().lock().unwrap_or_else(PoisonError::into_inner).entry(self.clone()).or_insert(false);
+                html::paste_text(&body).map_err(|e| e.context(&url))
+            }
+            Backend::PasteRs => {
+                let res = client.get(&url).send().await?;
+                if !res.status().is_success() {
+                    return Err(Error::status(res.status(), format!("Failed to download {}", url)));
+                }
+                Ok(res.text().await?)
+            }
+            Backend::Local(_) => fs::read_to_string(&url).map_err(|e| Error::io(format!("Failed to read {}", url), e)),
+        }
+    }
+
+    // Fetches `id` from the raw endpoint, None when the backend has none or
+    // it didn't answer with plain text
+    async fn fetch_raw(&self, client: &Client, id: &str) -> Result<Option<String>, Error> {
+        let Some(raw_url) = self.raw_url(id) else {
+            return Ok(None);
+        };
+        if raw_support().lock().unwrap_or_else(PoisonError::into_inner).get(self) == Some(&false) {
+            return Ok(None);
+        }
+        let res = client.get(&raw_url).send().await?;
+        let plain_text = res.headers().get(reqwest::header::CONTENT_TYPE)
+            .and_then(|value| value.to_str().ok())
+            .is_some_and(|value| value.starts_with("text/plain"));
+        if !res.status().is_success() || !plain_text {
+            debug!("{} answered {} without plain text, reading the page instead", raw_url, res.status());
+            return Ok(None);
+        }
+        raw_support().lock().unwrap_or_else(PoisonError::into_inner).insert(self.clone(), true);
+        Ok(Some(res.text().await?))
+    }
+
+    // Checks that `id` can still be fetched without downloading it
+    pub async fn paste_exists(&self, client: &Client, id: &str) -> Result<bool, Error> {
+        let url = self.paste_url(id);
+        if let Backend::Local(_) = self {
+            return Ok(Path::new(&url).is_file());
+        }
+        let mut res = client.head(&url).send().await?;
+        // Fall back to a GET on hosts that don't answer HEAD
+        if res.status() == reqwest::StatusCode::METHOD_NOT_ALLOWED {
+            res = client.get(&url).send().await?;
+        }
+        match res.status() {
+            status if status.is_success() => Ok(true),
+            reqwest::StatusCode::NOT_FOUND | reqwest::StatusCode::GONE => Ok(false),
+            status => Err(Error::status(status, format!("Checking {} failed", url))),
+        }
+    }
+
+    // Largest number of `encoding` characters a single paste can hold
+    pub async fn max_paste_size(&self, client: &Client, encoding: Encoding) -> usize {
+        let (known_good, upper_bound) = match self.paste_limit() {
+            PasteLimit::Fixed(bytes) => return bytes / encoding.bytes_per_char(),
+            PasteLimit::Probe { known_good, upper_bound } => (known_good, upper_bound),
+        };
+        // Parts are never larger than the configured chunk size, so there's
+        // no point uploading test pastes above it
+        let max_chunk_size = settings::current().max_chunk_size.unwrap_or(usize::MAX);
+        if max_chunk_size <= known_good {
+            return max_chunk_size;
+        }
+        let clamped = max_chunk_size < upper_bound;
+        let upper_bound = upper_bound.min(max_chunk_size);
+        let endpoint = self.endpoint().unwrap_or_default();
+        let is_key = |limit: &ProbedLimit| limit.backend == *self && limit.endpoint == endpoint && limit.encoding == encoding;
+        let now = unix_now();
+        let probed = probed_limits().lock().unwrap_or_else(PoisonError::into_inner).iter()
+            .find(|limit| is_key(limit) && now.saturating_sub(limit.probed_at) < PROBE_MAX_AGE)
+            .map(|limit| limit.size);
+        if let Some(size) = probed {
+            return size;
+        }
+
+        let size = if self.accepts(client, encoding, upper_bound).await {
+            upper_bound
+        } else {
+            let (mut low, mut high) = (known_good, upper_bound);
+            for _ in 0..PROBE_STEPS {
+                let mid = low + (high - low) / 2;
+                if self.accepts(client, encoding, mid).await {
+                    low = mid;
+                } else {
+                    high = mid;
+                }
+            }
+            low
+        };
+        info!("Probed {:?} paste limit for {:?}: {} characters", self, encoding, size);
+        // Accepting the clamped bound says nothing about the host's own limit
+        if clamped && size == upper_bound {
+            return size;
+        }
+        let mut limits = probed_limits().lock().unwrap_or_else(PoisonError::into_inner);
+        limits.retain(|limit| !is_key(limit));
+        limits.push(ProbedLimit { backend: self.clone(), endpoint, encoding, size, probed_at: now });
+        if let Err(e) = save_probed_limits(&limits) {
+            warn!("Failed to save the probed paste limits: {}", e);
+        }
+        size
+    }
+
+    // Uploads a short-lived paste of `size` characters of random encoded data
+    // and fetches it back. A host that answers 2xx but cuts or changes the
+    // paste doesn't count as accepting it.
+    async fn accepts(&self, client: &Client, encoding: Encoding, size: usize) -> bool {
+        let mut data = vec![0u8; encoding.raw_chunk_size(size)];
+        if openssl::rand::rand_bytes(&mut data).is_err() {
+            return false;
+        }
+        let text = encoding.encode(&data);
+        let stored = match self.post_paste(client, text.clone(), "10m").await {
+            Ok(id) => self.fetch_paste(client, &id).await,
+            Err(e) => Err(e),
+        };
+        match stored {
+            Ok(stored) if stored == text => true,
+            Ok(stored) => {
+                debug!("{:?} kept {} of {} characters", self, stored.len(), text.len());
+                false
+            }
+            Err(e) => {
+                debug!("{:?} didn't take {} characters: {}", self, text.len(), e);
+                false
+            }
+        }
+    }
+}
+
+// Share links are the innomi title on its own, as they've always been, or
+// prefixed with the backend for the others
+impl fmt::Display for Location {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match &self.backend {
+            Backend::Innomi => write!(f, "{}", self.id),
+            Backend::PasteRs => write!(f, "paste_rs:{}", self.id),
+            Backend::Local(dir) => write!(f, "local:{}", dir.join(&self.id).display()),
+        }
+    }
+}
+
+impl Location {
+    pub fn parse(link: &str) -> Result<Self, Error> {
+        let link = link.trim();
+        let location = Self::parse_unchecked(link)?;
+        location.check_id().map_err(|e| e.context(format!("Invalid link {}", link)))?;
+        Ok(location)
+    }
+
+    fn parse_unchecked(link: &str) -> Result<Self, Error> {
+        if let Some(id) = link.strip_prefix("paste_rs:") {
+            return Ok(Location { backend: Backend::PasteRs, id: id.to_string() });
+        }
+        if let Some(path) = link.strip_prefix("local:") {
+            let path = PathBuf::from(path);
+            let id = path.file_name().and_then(|name| name.to_str())
+                .ok_or_else(|| Error::invalid(format!("Invalid local link {}", link)))?
+                .to_string();
+            let dir = path.parent().map(PathBuf::from).unwrap_or_default();
+            return Ok(Location { backend: Backend::Local(dir), id });
+        }
+        if link.is_empty() {
+            return Err(Error::invalid("The share link is empty"));
+        }
+        Ok(Location { backend: Backend::Innomi, id: link.to_string() })
+    }
+
+    // Ids are single names. One with a separator could make a local backend
+    // read any file on disk, or a paste host serve some other page.
+    pub fn check_id(&self) -> Result<(), Error> {
+        let id = self.id.as_str();
+        if id.is_empty() || id == "." || id == ".." || id.contains(['/', '\\', ':']) || Path::new(id).is_absolute() {
+            return Err(Error::invalid(format!("{:?} is not a paste id", id)));
+        }
+        Ok(())
+    }
+
+    pub async fn fetch(&self, client: &Client) -> Result<String, Error> {
+        self.backend.fetch_paste(client, &self.id).await
+    }
+
+    pub async fn exists(&self, client: &Client) -> Result<bool, Error> {
+        self.backend.paste_exists(client, &self.id).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::encoding::ALL_ENCODINGS;
+
+    #[test]
+    fn preserves_depends_on_the_backend_and_encoding() {
+        for encoding in ALL_ENCODINGS {
+            assert!(Backend::Innomi.preserves(encoding), "{:?}", encoding);
+            assert!(Backend::Local(PathBuf::from("/tmp")).preserves(encoding), "{:?}", encoding);
+        }
+        assert!(Backend::PasteRs.preserves(Encoding::Base64));
+        assert!(Backend::PasteRs.preserves(Encoding::Z85));
+        assert!(!Backend::PasteRs.preserves(Encoding::Base32768));
+    }
+
+    #[tokio::test]
+    async fn fixed_limits_are_bytes_whatever_the_encoding() {
+        let client = Client::new();
+        let local = Backend::Local(PathBuf::from("/tmp"));
+        assert_eq!(local.max_paste_size(&client, Encoding::Z85).await, 16 * 1024 * 1024);
+        let chars = local.max_paste_size(&client, Encoding::Base32768).await;
+        let part = Encoding::Base32768.encode(&vec![0xAB; Encoding::Base32768.raw_chunk_size(chars)]);
+        assert!(part.len() <= 16 * 1024 * 1024);
+    }
+
+    #[test]
+    fn expire_duration_refuses_values_that_overflow() {
+        assert!(expire_duration("99999999999999999d").is_err());
+        assert_eq!(expire_duration("14d").unwrap().map(|lifetime| lifetime.as_secs()), Some(14 * 24 * 60 * 60));
+    }
+
+    #[test]
+    fn preferred_encodings_are_preserved() {
+        for backend in [Backend::Innomi, Backend::PasteRs, Backend::Local(PathBuf::from("/tmp"))] {
+            assert!(backend.preserves(backend.preferred_encoding()), "{:?}", backend);
+        }
+    }
+}
@@ -0,0 +1,268 @@
+use std::io::{Read, Write};
+use serde::{Serialize, Deserialize};
+use flate2::Compression as DeflateLevel;
+use flate2::read::DeflateDecoder;
+use flate2::write::{DeflateEncoder, DeflateDecoder as DeflateWriteDecoder};
+use tracing::{debug, warn};
+use crate::error::Error;
+
+// How much of the content is test-compressed before committing to a codec
+const SAMPLE_SIZE: usize = 64 * 1024; // 64KB
+// The sample has to shrink to at least this fraction of its size, otherwise
+// the data is treated as already compressed (jpg, mp4, zip, ...)
+const MAX_SAMPLE_RATIO: f64 = 0.9;
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Codec {
+    #[default]
+    None,
+    Zstd,
+    Deflate,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug)]
+pub struct CompressionOptions {
+    pub codec: Codec,
+    // Codec specific level, zstd 1-22 (default 3) and deflate 0-9 (default 6)
+    pub level: Option<i32>,
+}
+
+impl Codec {
+    fn level(self, level: Option<i32>) -> i32 {
+        match self {
+            Codec::None => 0,
+            Codec::Zstd => level.unwrap_or(3).clamp(1, 22),
+            Codec::Deflate => level.unwrap_or(6).clamp(0, 9),
+        }
+    }
+}
+
+pub fn compress(data: &[u8], codec: Codec, level: Option<i32>) -> Result<Vec<u8>, Error> {
+    let level = codec.level(level);
+    match codec {
+        Codec::None => Ok(data.to_vec()),
+        Codec::Zstd => zstd::encode_all(data, level)
+            .map_err(|e| Error::io("zstd compression failed", e)),
+        Codec::Deflate => {
+            let mut encoder = DeflateEncoder::new(Vec::new(), DeflateLevel::new(level as u32));
+            encoder.write_all(data).map_err(|e| Error::io("deflate compression failed", e))?;
+            encoder.finish().map_err(|e| Error::io("deflate compression failed", e))
+        }
+    }
+}
+
+pub fn decompress(data: &[u8], codec: Codec) -> Result<Vec<u8>, Error> {
+    match codec {
+        Codec::None => Ok(data.to_vec()),
+        Codec::Zstd => zstd::decode_all(data)
+            .map_err(|e| Error::Decode(format!("zstd decompression failed: {}", e))),
+        Codec::Deflate => {
+            let mut decompressed = Vec::new();
+            DeflateDecoder::new(data).read_to_end(&mut decompressed)
+                .map_err(|e| Error::Decode(format!("deflate decompression failed: {}", e)))?;
+            Ok(decompressed)
+        }
+    }
+}
+
+// Compresses data fed in pieces, the output can be taken as it's produced so
+// a stream never has to be held in memory. Gives the same format as `compress`.
+pub enum Compressor {
+    None(Vec<u8>),
+    Zstd(zstd::stream::write::Encoder<'static, Vec<u8>>),
+    Deflate(DeflateEncoder<Vec<u8>>),
+}
+
+impl Compressor {
+    pub fn new(codec: Codec, level: Option<i32>) -> Result<Self, Error> {
+        let level = codec.level(level);
+        Ok(match codec {
+            Codec::None => Compressor::None(Vec::new()),
+            Codec::Zstd => Compressor::Zstd(zstd::stream::write::Encoder::new(Vec::new(), level)
+                .map_err(|e| Error::io("zstd compression failed", e))?),
+            Codec::Deflate => Compressor::Deflate(DeflateEncoder::new(Vec::new(), DeflateLevel::new(level as u32))),
+        })
+    }
+
+    pub fn write(&mut self, data: &[u8]) -> Result<(), Error> {
+        match self {
+            Compressor::None(output) => output.extend_from_slice(data),
+            Compressor::Zstd(encoder) => encoder.write_all(data).map_err(|e| Error::io("zstd compression failed", e))?,
+            Compressor::Deflate(encoder) => encoder.write_all(data).map_err(|e| Error::io("deflate compression failed", e))?,
+        }
+        Ok(())
+    }
+
+    // Takes the output produced so far in whole chunks of `size` bytes
+    pub fn take_chunks(&mut self, size: usize) -> Vec<Vec<u8>> {
+        let output = match self {
+            Compressor::None(output) => output,
+            Compressor::Zstd(encoder) => encoder.get_mut(),
+            Compressor::Deflate(encoder) => encoder.get_mut(),
+        };
+        let whole = output.len() / size * size;
+        output.drain(..whole).collect::<Vec<u8>>().chunks(size).map(<[u8]>::to_vec).collect()
+    }
+
+    // Ends the stream, returns whatever output hasn't been taken yet
+    pub fn finish(self) -> Result<Vec<u8>, Error> {
+        match self {
+            Compressor::None(output) => Ok(output),
+            Compressor::Zstd(encoder) => encoder.finish().map_err(|e| Error::io("zstd compression failed", e)),
+            Compressor::Deflate(encoder) => encoder.finish().map_err(|e| Error::io("deflate compression failed", e)),
+        }
+    }
+}
+
+// The reverse of `Compressor`, for rebuilding a file part by part
+pub enum Decompressor {
+    None(Vec<u8>),
+    Zstd(zstd::stream::write::Decoder<'static, Vec<u8>>),
+    Deflate(DeflateWriteDecoder<Vec<u8>>),
+}
+
+impl Decompressor {
+    pub fn new(codec: Codec) -> Result<Self, Error> {
+        Ok(match codec {
+            Codec::None => Decompressor::None(Vec::new()),
+            Codec::Zstd => Decompressor::Zstd(zstd::stream::write::Decoder::new(Vec::new())
+                .map_err(|e| Error::Decode(format!("zstd decompression failed: {}", e)))?),
+            Codec::Deflate => Decompressor::Deflate(DeflateWriteDecoder::new(Vec::new())),
+        })
+    }
+
+    pub fn write(&mut self, data: &[u8]) -> Result<(), Error> {
+        match self {
+            Decompressor::None(output) => output.extend_from_slice(data),
+            Decompressor::Zstd(decoder) => decoder.write_all(data).map_err(|e| Error::Decode(format!("zstd decompression failed: {}", e)))?,
+            Decompressor::Deflate(decoder) => decoder.write_all(data).map_err(|e| Error::Decode(format!("deflate decompression failed: {}", e)))?,
+        }
+        Ok(())
+    }
+
+    // Takes the output produced so far
+    pub fn take_output(&mut self) -> Vec<u8> {
+        match self {
+            Decompressor::None(output) => std::mem::take(output),
+            Decompressor::Zstd(decoder) => std::mem::take(decoder.get_mut()),
+            Decompressor::Deflate(decoder) => std::mem::take(decoder.get_mut()),
+        }
+    }
+
+    pub fn finish(self) -> Result<Vec<u8>, Error> {
+        match self {
+            Decompressor::None(output) => Ok(output),
+            Decompressor::Zstd(mut decoder) => {
+                decoder.flush().map_err(|e| Error::Decode(format!("zstd decompression failed: {}", e)))?;
+                Ok(decoder.into_inner())
+            }
+            Decompressor::Deflate(decoder) => decoder.finish().map_err(|e| Error::Decode(format!("deflate decompression failed: {}", e))),
+        }
+    }
+}
+
+// Picks the codec to actually use for content starting with `data`: the
+// requested one, unless a sample from the start, middle and end of `data`
+// doesn't compress well. Uploads are streamed, so `data` is only the first
+// chunk and the rest of the content isn't looked at.
+pub fn choose_codec(data: &[u8], options: Option<CompressionOptions>) -> Codec {
+    let options = match options {
+        Some(options) if options.codec != Codec::None => options,
+        _ => return Codec::None,
+    };
+
+    let sample = sample(data);
+    if sample.is_empty() {
+        return Codec::None;
+    }
+    match compress(&sample, options.codec, Some(1)) {
+        Ok(compressed) if (compressed.len() as f64) <= sample.len() as f64 * MAX_SAMPLE_RATIO => options.codec,
+        Ok(compressed) => {
+            debug!("Skipping compression, sample only shrank from {} to {} bytes", sample.len(), compressed.len());
+            Codec::None
+        }
+        Err(e) => {
+            warn!("Skipping compression, sample compression failed: {}", e);
+            Codec::None
+        }
+    }
+}
+
+fn sample(data: &[u8]) -> Vec<u8> {
+    if data.len() <= SAMPLE_SIZE {
+        return data.to_vec();
+    }
+    let slice = SAMPLE_SIZE / 3;
+    let middle = data.len() / 2 - slice / 2;
+    let mut sample = Vec::with_capacity(slice * 3);
+    sample.extend_from_slice(&data[..slice]);
+    sample.extend_from_slice(&data[middle..middle + slice]);
+    sample.extend_from_slice(&data[data.len() - slice..]);
+    sample
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CODECS: [Codec; 2] = [Codec::Zstd, Codec::Deflate];
+
+    fn text(length: usize) -> Vec<u8> {
+        b"The quick brown fox jumps over the lazy dog. ".iter().cycle().take(length).copied().collect()
+    }
+
+    fn options(codec: Codec) -> Option<CompressionOptions> {
+        Some(CompressionOptions { codec, level: None })
+    }
+
+    #[test]
+    fn round_trips_and_shrinks_text() {
+        for codec in CODECS {
+            let data = text(100_000);
+            let compressed = compress(&data, codec, None).unwrap();
+            assert!(compressed.len() < data.len() / 10, "{:?}", codec);
+            assert_eq!(decompress(&compressed, codec).unwrap(), data, "{:?}", codec);
+        }
+    }
+
+    #[test]
+    fn compressed_data_is_left_alone() {
+        let mut random = vec![0u8; 200_000];
+        openssl::rand::rand_bytes(&mut random).unwrap();
+        // Hex shrinks by half, what's left after compressing it doesn't
+        let compressed = compress(hex::encode(&random).as_bytes(), Codec::Zstd, None).unwrap();
+        for codec in CODECS {
+            assert_eq!(choose_codec(&text(200_000), options(codec)), codec);
+            assert_eq!(choose_codec(&random, options(codec)), Codec::None, "{:?}", codec);
+            assert_eq!(choose_codec(&compressed, options(codec)), Codec::None, "{:?}", codec);
+        }
+        assert_eq!(choose_codec(&text(1000), None), Codec::None);
+        assert_eq!(choose_codec(&[], options(Codec::Zstd)), Codec::None);
+    }
+
+    #[test]
+    fn streamed_chunks_decompress_part_by_part() {
+        let data = text(500_000);
+        for codec in CODECS {
+            let mut compressor = Compressor::new(codec, Some(1)).unwrap();
+            let mut chunks = vec![];
+            for piece in data.chunks(7_000) {
+                compressor.write(piece).unwrap();
+                chunks.extend(compressor.take_chunks(512));
+            }
+            assert!(chunks.iter().all(|chunk| chunk.len() == 512));
+            chunks.push(compressor.finish().unwrap());
+            assert_eq!(decompress(&chunks.concat(), codec).unwrap(), data, "{:?}", codec);
+
+            let mut decompressor = Decompressor::new(codec).unwrap();
+            let mut rebuilt = vec![];
+            for chunk in &chunks {
+                decompressor.write(chunk).unwrap();
+                rebuilt.extend(decompressor.take_output());
+            }
+            rebuilt.extend(decompressor.finish().unwrap());
+            assert_eq!(rebuilt, data, "{:?}", codec);
+        }
+    }
+}
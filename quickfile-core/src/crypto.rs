@@ -0,0 +1,47 @@
+use openssl::symm::{Cipher, Crypter, Mode};
+use crate::error::Error;
+
+// Length of the random IV that starts every encrypted buffer
+const IV_LENGTH: usize = 16;
+const PBKDF2_ITERATIONS: usize = 100_000;
+
+// 256 bit AES key from a password
+pub fn derive_key(password: &str, salt: &[u8]) -> Result<Vec<u8>, Error> {
+    let mut key = vec![0u8; 32];
+    openssl::pkcs5::pbkdf2_hmac(password.as_bytes(), salt, PBKDF2_ITERATIONS, openssl::hash::MessageDigest::sha256(), &mut key)
+        .map_err(|e| Error::Crypto(format!("Key derivation failed: {}", e)))?;
+    Ok(key)
+}
+
+// AES-256-CBC with a random IV, returns the IV followed by the ciphertext
+pub fn encrypt(data: &[u8], key: &[u8]) -> Result<Vec<u8>, Error> {
+    let mut iv = vec![0; IV_LENGTH];
+    openssl::rand::rand_bytes(&mut iv)?;
+    let mut encrypted = crypt(Mode::Encrypt, data, key, &iv)?;
+    let mut result = iv;
+    result.append(&mut encrypted);
+    Ok(result)
+}
+
+// Reverses `encrypt`, a wrong key shows up as a padding error
+pub fn decrypt(encrypted_data: &[u8], key: &[u8]) -> Result<Vec<u8>, Error> {
+    if encrypted_data.len() < IV_LENGTH {
+        return Err(Error::Crypto("Encrypted data is shorter than its IV".to_string()));
+    }
+    let (iv, encrypted_text) = encrypted_data.split_at(IV_LENGTH);
+    crypt(Mode::Decrypt, encrypted_text, key, iv)
+}
+
+fn crypt(mode: Mode, data: &[u8], key: &[u8], iv: &[u8]) -> Result<Vec<u8>, Error> {
+    let cipher = Cipher::aes_256_cbc();
+    let failed = |e: openssl::error::ErrorStack| match mode {
+        Mode::Encrypt => Error::Crypto(format!("Encryption failed: {}", e)),
+        Mode::Decrypt => Error::Crypto(format!("Decryption failed, wrong key or corrupted data: {}", e)),
+    };
+    let mut crypter = Crypter::new(cipher, mode, key, Some(iv)).map_err(failed)?;
+    let mut output = vec![0; data.len() + cipher.block_size()];
+    let mut count = crypter.update(data, &mut output).map_err(failed)?;
+    count += crypter.finalize(&mut output[count..]).map_err(failed)?;
+    output.truncate(count);
+    Ok(output)
+}
@@ -0,0 +1,188 @@
+use serde::{Serialize, Deserialize};
+use crate::error::Error;
+
+// Text encodings a chunk can be stored as. Each chunk is encoded on its own,
+// so every part can be decoded without its neighbours.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+#[serde(rename_all = "lowercase")]
+pub enum Encoding {
+    // 3 bytes -> 4 chars, plain ASCII
+    #[default]
+    Base64,
+    // 4 bytes -> 5 chars, printable ASCII including punctuation such as <>&
+    Z85,
+    // 15 bits -> 1 char from the CJK/Hangul blocks, needs a host that keeps
+    // arbitrary UTF-8 and limits pastes by characters rather than bytes
+    Base32768,
+}
+
+pub const ALL_ENCODINGS: [Encoding; 3] = [Encoding::Base64, Encoding::Z85, Encoding::Base32768];
+
+impl Encoding {
+    // UTF-8 bytes each character of the encoded text takes
+    pub fn bytes_per_char(self) -> usize {
+        match self {
+            Encoding::Base64 | Encoding::Z85 => 1,
+            // Every character is in the BMP above U+0800
+            Encoding::Base32768 => 3,
+        }
+    }
+
+    // Largest number of raw bytes whose encoding fits in `max_chars` characters
+    pub fn raw_chunk_size(self, max_chars: usize) -> usize {
+        let size = match self {
+            Encoding::Base64 => max_chars / 4 * 3,
+            Encoding::Z85 => max_chars / 5 * 4,
+            Encoding::Base32768 => max_chars / 8 * 15,
+        };
+        size.max(1)
+    }
+
+    pub fn encode(self, data: &[u8]) -> String {
+        match self {
+            Encoding::Base64 => base64::encode(data),
+            Encoding::Z85 => z85::encode(data),
+            Encoding::Base32768 => base32768::encode(data),
+        }
+    }
+
+    pub fn decode(self, text: &str) -> Result<Vec<u8>, Error> {
+        match self {
+            Encoding::Base64 => base64::decode(text).map_err(|e| Error::Decode(format!("Invalid base64: {}", e))),
+            Encoding::Z85 => z85::decode(text).map_err(|e| Error::Decode(format!("Invalid z85: {}", e))),
+            Encoding::Base32768 => base32768::decode(text),
+        }
+    }
+}
+
+// Packs 15 bits into each character. Full characters come from three CJK and
+// Hangul ranges, a trailing group of up to 7 bits uses a separate 128 char
+// range. Padding bits are set to 1 and dropped on decode since they never
+// add up to a whole byte.
+mod base32768 {
+    use crate::error::Error;
+
+    // (first code point, number of code points) for the 15 bit repertoire
+    const BLOCKS: [(u32, u32); 3] = [(0x4E00, 20992), (0xAC00, 11172), (0x3400, 604)];
+    // 7 bit repertoire for the final character, also in CJK extension A
+    const SHORT_START: u32 = 0x3400 + 1024;
+
+    pub fn encode(data: &[u8]) -> String {
+        let mut out = String::with_capacity(data.len() * 8 / 15 + 1);
+        let mut buffer: u32 = 0;
+        let mut bits = 0;
+        for &byte in data {
+            buffer = (buffer << 8) | byte as u32;
+            bits += 8;
+            if bits >= 15 {
+                bits -= 15;
+                out.push(long_char((buffer >> bits) & 0x7FFF));
+                buffer &= (1 << bits) - 1;
+            }
+        }
+        if bits > 7 {
+            let pad = 15 - bits;
+            out.push(long_char((buffer << pad) | ((1 << pad) - 1)));
+        } else if bits > 0 {
+            let pad = 7 - bits;
+            out.push(char::from_u32(SHORT_START + ((buffer << pad) | ((1 << pad) - 1))).unwrap());
+        }
+        out
+    }
+
+    pub fn decode(text: &str) -> Result<Vec<u8>, Error> {
+        let mut out = Vec::with_capacity(text.len() * 15 / 24 + 1);
+        let mut buffer: u32 = 0;
+        let mut bits = 0;
+        for (position, c) in text.chars().enumerate() {
+            let (value, width) = match long_value(c as u32) {
+                Some(value) => (value, 15),
+                None if (SHORT_START..SHORT_START + 128).contains(&(c as u32)) => (c as u32 - SHORT_START, 7),
+                None => return Err(Error::Decode(format!("Invalid base32768 character {:?} at {}", c, position))),
+            };
+            buffer = (buffer << width) | value;
+            bits += width;
+            while bits >= 8 {
+                bits -= 8;
+                out.push((buffer >> bits) as u8);
+                buffer &= (1 << bits) - 1;
+            }
+        }
+        Ok(out)
+    }
+
+    fn long_char(mut value: u32) -> char {
+        for (start, len) in BLOCKS {
+            if value < len {
+                return char::from_u32(start + value).unwrap();
+            }
+            value -= len;
+        }
+        unreachable!("value is at most 15 bits")
+    }
+
+    fn long_value(code: u32) -> Option<u32> {
+        let mut offset = 0;
+        for (start, len) in BLOCKS {
+            if (start..start + len).contains(&code) {
+                return Some(offset + code - start);
+            }
+            offset += len;
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Every byte value, in an order that mixes high and low bits
+    fn sample(length: usize) -> Vec<u8> {
+        (0..length).map(|i| (i * 167 + 13) as u8).collect()
+    }
+
+    #[test]
+    fn every_encoding_round_trips_every_length() {
+        for encoding in ALL_ENCODINGS {
+            for length in (0..64).chain([255, 256, 1000, 4096]) {
+                let data = sample(length);
+                let text = encoding.encode(&data);
+                assert_eq!(encoding.decode(&text).unwrap(), data, "{:?} with {} bytes", encoding, length);
+            }
+        }
+    }
+
+    #[test]
+    fn chunks_fit_in_the_characters_they_are_sized_for() {
+        for encoding in ALL_ENCODINGS {
+            for max_chars in [8, 100, 1000, 1024 * 1024] {
+                let chunk = sample(encoding.raw_chunk_size(max_chars));
+                assert!(encoding.encode(&chunk).chars().count() <= max_chars, "{:?} with {} characters", encoding, max_chars);
+            }
+        }
+    }
+
+    #[test]
+    fn base32768_uses_one_character_per_15_bits() {
+        assert_eq!(Encoding::Base32768.encode(&sample(15)).chars().count(), 8);
+        // A trailing group of 7 bits or less takes a character from the short range
+        let text = Encoding::Base32768.encode(&sample(17));
+        assert_eq!(text.chars().count(), 10);
+        assert!((0x3800..0x3880).contains(&(text.chars().last().unwrap() as u32)));
+    }
+
+    #[test]
+    fn z85_keeps_its_punctuation() {
+        let text = Encoding::Z85.encode(&[0x86, 0x4F, 0xD2, 0x6F, 0xB5, 0x59, 0xF7, 0x5B]);
+        assert_eq!(text, "HelloWorld");
+        assert_eq!(Encoding::Z85.decode("HelloWorld").unwrap(), [0x86, 0x4F, 0xD2, 0x6F, 0xB5, 0x59, 0xF7, 0x5B]);
+    }
+
+    #[test]
+    fn text_from_another_encoding_is_a_decode_error() {
+        assert!(matches!(Encoding::Base32768.decode("aGk="), Err(Error::Decode(_))));
+        assert!(matches!(Encoding::Base64.decode("一丁"), Err(Error::Decode(_))));
+        assert!(matches!(Encoding::Z85.decode("a\"b~c"), Err(Error::Decode(_))));
+    }
+}
@@ -0,0 +1,177 @@
+use std::fmt::Display;
+use serde::ser::{Serialize, SerializeStruct, Serializer};
+use thiserror::Error;
+
+// Everything the pipeline can fail with. The frontend and the CLI get the
+// kind as `code`, the message is meant for people.
+#[derive(Error, Debug)]
+pub enum Error {
+    // Reading or writing local files
+    #[error("{0}")]
+    Io(String),
+    // A backend refused the request, or couldn't be reached when `status` is None
+    #[error("{message}")]
+    Http { status: Option<u16>, message: String },
+    // A paste host page doesn't have the expected layout
+    #[error("{0}")]
+    ParseHtml(String),
+    // Text that should hold a part or a manifest doesn't decode
+    #[error("{0}")]
+    Decode(String),
+    #[error("{0}")]
+    Crypto(String),
+    // A file can't be rebuilt as its manifest describes, `part` counts from 1
+    #[error("{message}")]
+    Integrity { file: String, part: Option<usize>, message: String },
+    // The share is gone from the host
+    #[error("{0}")]
+    Expired(String),
+    #[error("Cancelled")]
+    Cancelled,
+    // Arguments that can't work, e.g. an unknown file id
+    #[error("{0}")]
+    Invalid(String),
+    // A share asks for something only a crafted one would, e.g. a path outside
+    // the destination or a redirect loop
+    #[error("{0}")]
+    Refused(String),
+    // A bug rather than bad input, e.g. a part task that panicked
+    #[error("{0}")]
+    Internal(String),
+}
+
+impl Error {
+    pub fn code(&self) -> &'static str {
+        match self {
+            Error::Io(_) => "io",
+            Error::Http { .. } => "http",
+            Error::ParseHtml(_) => "parseHtml",
+            Error::Decode(_) => "decode",
+            Error::Crypto(_) => "crypto",
+            Error::Integrity { .. } => "integrity",
+            Error::Expired(_) => "expired",
+            Error::Cancelled => "cancelled",
+            Error::Invalid(_) => "invalid",
+            Error::Refused(_) => "refused",
+            Error::Internal(_) => "internal",
+        }
+    }
+
+    pub fn io(context: impl Display, e: impl Display) -> Self {
+        Error::Io(format!("{}: {}", context, e))
+    }
+
+    pub fn status(status: reqwest::StatusCode, message: impl Display) -> Self {
+        Error::Http { status: Some(status.as_u16()), message: format!("{}: {}", message, status) }
+    }
+
+    pub fn integrity(file: &str, part: Option<usize>, message: impl Into<String>) -> Self {
+        Error::Integrity { file: file.to_string(), part, message: message.into() }
+    }
+
+    pub fn invalid(message: impl Into<String>) -> Self {
+        Error::Invalid(message.into())
+    }
+
+    pub fn refused(message: impl Into<String>) -> Self {
+        Error::Refused(message.into())
+    }
+
+    // Adds what was being done to the message, keeping the kind
+    pub fn context(self, context: impl Display) -> Self {
+        self.map_message(|message| format!("{}: {}", context, message))
+    }
+
+    pub fn map_message(self, change: impl FnOnce(String) -> String) -> Self {
+        match self {
+            Error::Io(message) => Error::Io(change(message)),
+            Error::Http { status, message } => Error::Http { status, message: change(message) },
+            Error::ParseHtml(message) => Error::ParseHtml(change(message)),
+            Error::Decode(message) => Error::Decode(change(message)),
+            Error::Crypto(message) => Error::Crypto(change(message)),
+            Error::Integrity { file, part, message } => Error::Integrity { file, part, message: change(message) },
+            Error::Expired(message) => Error::Expired(change(message)),
+            Error::Cancelled => Error::Cancelled,
+            Error::Invalid(message) => Error::Invalid(change(message)),
+            Error::Refused(message) => Error::Refused(change(message)),
+            Error::Internal(message) => Error::Internal(change(message)),
+        }
+    }
+}
+
+impl From<std::io::Error> for Error {
+    fn from(e: std::io::Error) -> Self {
+        Error::Io(e.to_string())
+    }
+}
+
+impl From<reqwest::Error> for Error {
+    fn from(e: reqwest::Error) -> Self {
+        Error::Http { status: e.status().map(|status| status.as_u16()), message: e.to_string() }
+    }
+}
+
+impl From<serde_json::Error> for Error {
+    fn from(e: serde_json::Error) -> Self {
+        Error::Decode(e.to_string())
+    }
+}
+
+impl From<openssl::error::ErrorStack> for Error {
+    fn from(e: openssl::error::ErrorStack) -> Self {
+        Error::Crypto(e.to_string())
+    }
+}
+
+// { "code": "integrity", "message": "...", "file": "a.txt", "part": 3 }
+impl Serialize for Error {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut state = serializer.serialize_struct("Error", 5)?;
+        state.serialize_field("code", self.code())?;
+        state.serialize_field("message", &self.to_string())?;
+        match self {
+            Error::Http { status, .. } => state.serialize_field("status", status)?,
+            Error::Integrity { file, part, .. } => {
+                state.serialize_field("file", file)?;
+                state.serialize_field("part", part)?;
+            }
+            _ => {}
+        }
+        state.end()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn serializes_the_code_and_message() {
+        let error = Error::invalid("No backends to upload to").context("Failed to upload file_data.json");
+        assert_eq!(serde_json::to_value(&error).unwrap(), json!({
+            "code": "invalid",
+            "message": "Failed to upload file_data.json: No backends to upload to",
+        }));
+        assert_eq!(serde_json::to_value(Error::Cancelled).unwrap(), json!({ "code": "cancelled", "message": "Cancelled" }));
+    }
+
+    #[test]
+    fn serializes_the_fields_of_its_kind() {
+        let error = Error::status(reqwest::StatusCode::NOT_FOUND, "Failed to download");
+        assert_eq!(serde_json::to_value(&error).unwrap(), json!({
+            "code": "http",
+            "message": "Failed to download: 404 Not Found",
+            "status": 404,
+        }));
+        let error = Error::Http { status: None, message: "Connection refused".to_string() };
+        assert_eq!(serde_json::to_value(&error).unwrap()["status"], json!(null));
+        let error = Error::integrity("a.txt", Some(3), "Part 3 of 4 of a.txt: bad data");
+        assert_eq!(serde_json::to_value(&error).unwrap(), json!({
+            "code": "integrity",
+            "message": "Part 3 of 4 of a.txt: bad data",
+            "file": "a.txt",
+            "part": 3,
+        }));
+    }
+}
@@ -0,0 +1,509 @@
+use std::fs::{self, File, OpenOptions};
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+use serde::{Serialize, Deserialize};
+use serde::de::DeserializeOwned;
+use fs2::FileExt;
+use tracing::{info, warn};
+use crate::backend::{Backend, unix_now};
+use crate::error::Error;
+use crate::manifest::new_file_id;
+use crate::tree;
+
+pub const HISTORY_VERSION: u32 = 1;
+
+// Same as tauri.conf.json, Tauri keeps its app data under this name too
+pub(crate) const APP_IDENTIFIER: &str = "com.tauri.dev";
+const HISTORY_FILE: &str = "history.json";
+const RECEIVED_FILE: &str = "received.json";
+const LOCK_FILE: &str = "history.lock";
+// Where older versions of the app wrote the history, see `import_legacy_from`
+static LEGACY_HISTORY: OnceLock<PathBuf> = OnceLock::new();
+// Replaces the app data folder, see `use_history_dir`
+static HISTORY_DIR: OnceLock<PathBuf> = OnceLock::new();
+
+// One upload
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct HistoryEntry {
+    pub id: String,
+    // The share link, what `rebuild_files` takes as title
+    pub link: String,
+    // Links to the copies of the manifest on the other backends
+    #[serde(default)]
+    pub mirrors: Vec<String>,
+    pub files: Vec<HistoryFile>,
+    // Seconds since the Unix epoch, unknown for migrated entries
+    pub created_at: Option<u64>,
+    pub backends: Vec<Backend>,
+    // Expire value the parts were uploaded with, e.g. "1h"
+    pub expire: Option<String>,
+    pub expires_at: Option<u64>,
+    // Original size of all files together
+    pub size: Option<u64>,
+    // Characters uploaded for all parts, without mirrors
+    pub stored_size: Option<u64>,
+    // Result of the last liveness check, whether every file could still be rebuilt
+    #[serde(default)]
+    pub alive: Option<bool>,
+    #[serde(default)]
+    pub checked_at: Option<u64>,
+    // Derived from the above, see `HistoryEntry::status`
+    #[serde(default)]
+    pub status: ShareStatus,
+    // Upload the share again before it expires
+    #[serde(default)]
+    pub keep_alive: bool,
+    // Link that redirects to the current upload, it stays the same across
+    // refreshes. Needs a backend that can replace pastes.
+    #[serde(default)]
+    pub pointer: Option<String>,
+    // When the share was last uploaded again, `link` and `mirrors` are that upload
+    #[serde(default)]
+    pub refreshed_at: Option<u64>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum ShareStatus {
+    Live,
+    // Less than a quarter of its lifetime left
+    Expiring,
+    Expired,
+    // Migrated entries that were never checked
+    #[default]
+    Unknown,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct HistoryFile {
+    pub path: String,
+    pub size: Option<u64>,
+}
+
+// One rebuilt share
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct ReceivedEntry {
+    pub id: String,
+    // The link the share was rebuilt from
+    pub link: String,
+    pub received_at: u64,
+    pub destination: PathBuf,
+    // Only the files that were written, not skipped ones
+    pub files: Vec<ReceivedFile>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct ReceivedFile {
+    // Path in the share
+    pub path: String,
+    pub saved_to: PathBuf,
+    pub size: u64,
+    // Hex SHA-256 of the rebuilt file
+    pub sha256: String,
+}
+
+// State of a received file compared to when it was rebuilt
+#[derive(Serialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum FileState {
+    Unchanged,
+    Changed,
+    Missing,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FileCheck {
+    pub path: String,
+    pub saved_to: PathBuf,
+    pub state: FileState,
+}
+
+// Kinds of entries kept in the app data dir, each in its own file
+pub trait Entry: Serialize + DeserializeOwned + Clone {
+    const FILE: &'static str;
+
+    fn id(&self) -> &str;
+
+    // Reads the file, including shapes older versions wrote
+    fn parse(json: &str) -> Result<Vec<Self>, Error> {
+        let data: HistoryFileData<Self> = serde_json::from_str(json).map_err(|e| Error::Decode(format!("Failed to parse {}: {}", Self::FILE, e)))?;
+        data.check_version()?;
+        Ok(data.entries)
+    }
+
+    // Entries to start from when the file doesn't exist yet
+    fn initial() -> Result<Vec<Self>, Error> {
+        Ok(vec![])
+    }
+
+    // Called once the initial entries are saved
+    fn imported() {}
+}
+
+// Filters for `search`, every one that is set has to match
+#[derive(Deserialize, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct HistoryQuery {
+    // Part of a file path or the link, case insensitive
+    pub text: Option<String>,
+    // Uploaded at or after, seconds since the Unix epoch
+    pub from: Option<u64>,
+    // Uploaded before
+    pub to: Option<u64>,
+    pub backend: Option<Backend>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct HistoryFileData<T> {
+    version: u32,
+    entries: Vec<T>,
+}
+
+impl<T> HistoryFileData<T> {
+    fn check_version(&self) -> Result<(), Error> {
+        if self.version > HISTORY_VERSION {
+            return Err(Error::Decode(format!("History version {} is newer than supported version {}", self.version, HISTORY_VERSION)));
+        }
+        Ok(())
+    }
+}
+
+// Shapes the history file has had
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum RawHistory {
+    Current(HistoryFileData<HistoryEntry>),
+    // A plain list of untyped entries
+    Legacy(Vec<LegacyEntry>),
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum LegacyEntry {
+    // { "title": "<link>", "mirrors": [...], "file_names": [...] }
+    Titled {
+        title: String,
+        #[serde(default)]
+        mirrors: Vec<String>,
+        file_names: Vec<String>,
+    },
+    // The first version: { "files": "[\"<name>\", ...]", "file_data": "<link>" }
+    FileData {
+        files: String,
+        file_data: String,
+    },
+}
+
+impl HistoryEntry {
+    fn from_legacy(entry: LegacyEntry) -> Result<Self, Error> {
+        let (link, mirrors, file_names) = match entry {
+            LegacyEntry::Titled { title, mirrors, file_names } => (title, mirrors, file_names),
+            // `files` is itself JSON, fall back to a single name if it isn't
+            LegacyEntry::FileData { files, file_data } => {
+                let file_names = serde_json::from_str(&files).unwrap_or_else(|_| vec![files]);
+                (file_data, vec![], file_names)
+            }
+        };
+        Ok(HistoryEntry {
+            id: new_file_id()?,
+            link,
+            mirrors,
+            files: file_names.into_iter().map(|path| HistoryFile { path, size: None }).collect(),
+            created_at: None,
+            backends: vec![Backend::Innomi],
+            expire: None,
+            expires_at: None,
+            size: None,
+            stored_size: None,
+            alive: None,
+            checked_at: None,
+            status: ShareStatus::Unknown,
+            keep_alive: false,
+            pointer: None,
+            refreshed_at: None,
+        })
+    }
+
+    pub fn status(&self, now: u64) -> ShareStatus {
+        if self.alive == Some(false) || self.expires_at.is_some_and(|expires_at| expires_at <= now) {
+            return ShareStatus::Expired;
+        }
+        match (self.refreshed_at.or(self.created_at), self.expires_at) {
+            (Some(uploaded_at), Some(expires_at)) if (expires_at - now) * 4 < expires_at.saturating_sub(uploaded_at) => ShareStatus::Expiring,
+            // Known upload time and either a future expiry or none at all
+            (Some(_), _) => ShareStatus::Live,
+            _ if self.alive == Some(true) => ShareStatus::Live,
+            _ => ShareStatus::Unknown,
+        }
+    }
+
+    pub fn with_status(mut self, now: u64) -> Self {
+        self.status = self.status(now);
+        self
+    }
+
+    // The link to hand out, the pointer if there is one
+    pub fn share_link(&self) -> &str {
+        self.pointer.as_deref().unwrap_or(&self.link)
+    }
+
+    // Every copy of the current manifest, then the pointer
+    pub fn links(&self) -> Vec<String> {
+        std::iter::once(&self.link).chain(&self.mirrors).chain(&self.pointer).cloned().collect()
+    }
+}
+
+impl HistoryQuery {
+    fn matches(&self, entry: &HistoryEntry) -> bool {
+        if let Some(text) = &self.text {
+            let text = text.to_lowercase();
+            if !entry.link.to_lowercase().contains(&text)
+                && !entry.files.iter().any(|file| file.path.to_lowercase().contains(&text)) {
+                return false;
+            }
+        }
+        // Migrated entries have no date and never match a date range
+        if self.from.is_some() || self.to.is_some() {
+            let Some(created_at) = entry.created_at else { return false };
+            if self.from.is_some_and(|from| created_at < from) || self.to.is_some_and(|to| created_at >= to) {
+                return false;
+            }
+        }
+        if let Some(backend) = &self.backend {
+            if !entry.backends.contains(backend) {
+                return false;
+            }
+        }
+        true
+    }
+}
+
+impl Entry for HistoryEntry {
+    const FILE: &'static str = HISTORY_FILE;
+
+    fn id(&self) -> &str {
+        &self.id
+    }
+
+    fn parse(json: &str) -> Result<Vec<Self>, Error> {
+        match serde_json::from_str(json).map_err(|e| Error::Decode(format!("Failed to parse the history: {}", e)))? {
+            RawHistory::Current(data) => {
+                data.check_version()?;
+                Ok(data.entries)
+            }
+            RawHistory::Legacy(entries) => entries.into_iter().map(HistoryEntry::from_legacy).collect(),
+        }
+    }
+
+    // Imports the history older versions of the app left behind
+    fn initial() -> Result<Vec<Self>, Error> {
+        let Some(legacy_path) = LEGACY_HISTORY.get().filter(|path| path.is_file()) else {
+            return Ok(vec![]);
+        };
+        let json = fs::read_to_string(legacy_path).map_err(|e| Error::io(format!("Failed to read {}", legacy_path.display()), e))?;
+        let entries = Self::parse(&json)?;
+        info!("Migrating {} history entries from {}", entries.len(), legacy_path.display());
+        Ok(entries)
+    }
+
+    // Keep the old file around, renamed so it isn't imported again
+    fn imported() {
+        let Some(legacy_path) = LEGACY_HISTORY.get().filter(|path| path.is_file()) else {
+            return;
+        };
+        if let Err(e) = fs::rename(legacy_path, legacy_path.with_extension("json.migrated")) {
+            warn!("Failed to rename {} after migrating it: {}", legacy_path.display(), e);
+        }
+    }
+}
+
+impl ReceivedEntry {
+    // Hashes every saved file again and compares it with the recorded hash
+    pub fn verify(&self) -> Vec<FileCheck> {
+        self.files.iter().map(|file| {
+            let state = match fs::read(&file.saved_to) {
+                Ok(data) if sha256_hex(&data) == file.sha256 => FileState::Unchanged,
+                Ok(_) => FileState::Changed,
+                Err(_) => FileState::Missing,
+            };
+            FileCheck { path: file.path.clone(), saved_to: file.saved_to.clone(), state }
+        }).collect()
+    }
+}
+
+pub fn sha256_hex(data: &[u8]) -> String {
+    hex::encode(openssl::sha::sha256(data))
+}
+
+impl Entry for ReceivedEntry {
+    const FILE: &'static str = RECEIVED_FILE;
+
+    fn id(&self) -> &str {
+        &self.id
+    }
+}
+
+pub fn history_dir() -> Result<PathBuf, Error> {
+    if let Some(dir) = HISTORY_DIR.get() {
+        return Ok(dir.clone());
+    }
+    dirs::data_dir()
+        .map(|dir| dir.join(APP_IDENTIFIER))
+        .ok_or_else(|| Error::Io("No app data folder found for the history".to_string()))
+}
+
+// Keeps the history, and the logs, in `dir` instead of the app data folder.
+// Call it before anything reads the history, tests use it to stay away from
+// the user's files.
+pub fn use_history_dir(dir: PathBuf) {
+    if HISTORY_DIR.set(dir).is_err() {
+        warn!("The history folder was already set");
+    }
+}
+
+// Imports the history at `path`, from before it moved to `history_dir`, the
+// first time the history is read. Only the app calls this, the command line
+// client never had a history to import.
+pub fn import_legacy_from(path: PathBuf) {
+    if LEGACY_HISTORY.set(path).is_err() {
+        warn!("The legacy history path was already set");
+    }
+}
+
+// Holds an exclusive lock on the history until dropped, so concurrent
+// uploads, and the app and other processes, don't lose each other's entries
+struct HistoryLock(File);
+
+impl HistoryLock {
+    fn acquire(dir: &Path) -> Result<Self, Error> {
+        fs::create_dir_all(dir).map_err(|e| Error::io(format!("Failed to create {}", dir.display()), e))?;
+        let file = OpenOptions::new().create(true).truncate(false).write(true).open(dir.join(LOCK_FILE))
+            .map_err(|e| Error::io("Failed to open the history lock", e))?;
+        file.lock_exclusive().map_err(|e| Error::io("Failed to lock the history", e))?;
+        Ok(HistoryLock(file))
+    }
+}
+
+impl Drop for HistoryLock {
+    fn drop(&mut self) {
+        let _ = self.0.unlock();
+    }
+}
+
+// Reads the entries of one kind. Must be called with the lock held.
+fn read<T: Entry>(dir: &Path) -> Result<Vec<T>, Error> {
+    let path = dir.join(T::FILE);
+    if path.exists() {
+        let json = fs::read_to_string(&path).map_err(|e| Error::io(format!("Failed to read {}", path.display()), e))?;
+        return T::parse(&json);
+    }
+    let entries = T::initial()?;
+    if !entries.is_empty() {
+        write(dir, &entries)?;
+        T::imported();
+    }
+    Ok(entries)
+}
+
+fn write<T: Entry>(dir: &Path, entries: &[T]) -> Result<(), Error> {
+    let data = HistoryFileData { version: HISTORY_VERSION, entries: entries.to_vec() };
+    let json = serde_json::to_string_pretty(&data)?;
+    tree::write_atomically(dir, &dir.join(T::FILE), json.as_bytes())
+}
+
+// Every entry, newest first
+pub fn load<T: Entry>() -> Result<Vec<T>, Error> {
+    let dir = history_dir()?;
+    let _lock = HistoryLock::acquire(&dir)?;
+    let mut entries = read(&dir)?;
+    entries.reverse();
+    Ok(entries)
+}
+
+// Sent entries, newest first, with their current status
+pub fn list() -> Result<Vec<HistoryEntry>, Error> {
+    let now = unix_now();
+    Ok(load()?.into_iter().map(|entry: HistoryEntry| entry.with_status(now)).collect())
+}
+
+pub fn search(query: &HistoryQuery) -> Result<Vec<HistoryEntry>, Error> {
+    Ok(list()?.into_iter().filter(|entry| query.matches(entry)).collect())
+}
+
+pub fn find<T: Entry>(id: &str) -> Result<T, Error> {
+    load()?.into_iter()
+        .find(|entry: &T| entry.id() == id)
+        .ok_or_else(|| Error::invalid(format!("No history entry with id {}", id)))
+}
+
+// Applies `change` to the entries of one kind and saves them, all under the lock
+pub fn update<T: Entry, R>(change: impl FnOnce(&mut Vec<T>) -> R) -> Result<R, Error> {
+    let dir = history_dir()?;
+    let _lock = HistoryLock::acquire(&dir)?;
+    let mut entries = read(&dir)?;
+    let result = change(&mut entries);
+    write(&dir, &entries)?;
+    Ok(result)
+}
+
+pub fn record<T: Entry>(entry: T) -> Result<(), Error> {
+    update(|entries| entries.push(entry))
+}
+
+pub fn delete<T: Entry>(id: &str) -> Result<(), Error> {
+    let removed = update(|entries: &mut Vec<T>| {
+        let count = entries.len();
+        entries.retain(|entry| entry.id() != id);
+        count - entries.len()
+    })?;
+    if removed == 0 {
+        return Err(Error::invalid(format!("No history entry with id {}", id)));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // What the app wrote to its working directory before the history moved
+    const LEGACY_HISTORY: &str = include_str!("../tests/fixtures/legacy_history.json");
+
+    #[test]
+    fn parse_reads_the_legacy_history_the_app_left_behind() {
+        let entries = HistoryEntry::parse(LEGACY_HISTORY).unwrap();
+        assert_eq!(entries.len(), 17);
+        // The first version stored the file names as a JSON string
+        assert_eq!(entries[0].link, "ed9ytgosdrfrvd3ytm5o9jrc");
+        assert_eq!(entries[0].files[0].path, "test - Copy.jpg");
+        let last = entries.last().unwrap();
+        assert_eq!(last.link, "34ycqwedvszgqucdar9hks7a");
+        assert_eq!(last.files.iter().map(|file| file.path.as_str()).collect::<Vec<_>>(), ["test - Copy.jpg", "testing - Copy.jpg"]);
+        assert!(entries.iter().all(|entry| entry.backends == [Backend::Innomi] && entry.status == ShareStatus::Unknown));
+    }
+
+    #[test]
+    fn parse_keeps_mirrors_and_odd_file_lists_of_legacy_entries() {
+        let json = r#"[
+  { "title": "8n8269485jobpqq37hep76hx", "mirrors": ["paste_rs:Ab3xQ"], "file_names": ["a.txt"] },
+  { "files": "not json", "file_data": "86zbybqkemxgxgf9cwj4wpp2" }
+]"#;
+        let entries = HistoryEntry::parse(json).unwrap();
+        assert_eq!(entries[0].mirrors, ["paste_rs:Ab3xQ"]);
+        assert_eq!(entries[1].files[0].path, "not json");
+        assert_ne!(entries[0].id, entries[1].id);
+    }
+
+    #[test]
+    fn parse_reads_the_current_history_and_refuses_newer_versions() {
+        let json = r#"{ "version": 1, "entries": [] }"#;
+        assert!(HistoryEntry::parse(json).unwrap().is_empty());
+        let json = r#"{ "version": 2, "entries": [] }"#;
+        assert!(matches!(HistoryEntry::parse(json), Err(Error::Decode(_))));
+    }
+}
@@ -0,0 +1,38 @@
+// Reads pastes out of the pages innomi answers with. The layout isn't an API
+// and may change, so every miss says what was looked for.
+
+use scraper::{ElementRef, Html, Selector};
+use crate::error::Error;
+
+// Where the page shows the paste, HTML-escaped
+const CODE_SELECTOR: &str = "div#code";
+// "<id> - <site name>"
+const TITLE_SELECTOR: &str = "head > title";
+const TITLE_SEPARATOR: &str = " - ";
+
+fn select_first<'a>(document: &'a Html, selector: &str) -> Result<Option<ElementRef<'a>>, Error> {
+    let parsed = Selector::parse(selector).map_err(|e| Error::ParseHtml(format!("Invalid selector {}: {}", selector, e)))?;
+    Ok(document.select(&parsed).next())
+}
+
+// The text of the paste shown on a paste page
+pub fn paste_text(page: &str) -> Result<String, Error> {
+    let document = Html::parse_document(page);
+    let code = select_first(&document, CODE_SELECTOR)?
+        .ok_or_else(|| Error::ParseHtml(format!("The page has no {}, the paste host's layout may have changed", CODE_SELECTOR)))?;
+    Ok(code.text().collect())
+}
+
+// The id of a paste from the title of its page
+pub fn paste_title(page: &str) -> Result<String, Error> {
+    let document = Html::parse_document(page);
+    let title: String = select_first(&document, TITLE_SELECTOR)?
+        .ok_or_else(|| Error::ParseHtml("The page has no <title>, the paste host's layout may have changed".to_string()))?
+        .text()
+        .collect();
+    title.split(TITLE_SEPARATOR).next()
+        .map(str::trim)
+        .filter(|id| !id.is_empty() && title.contains(TITLE_SEPARATOR))
+        .map(str::to_string)
+        .ok_or_else(|| Error::ParseHtml(format!("The page title \"{}\" doesn't start with a paste id, the paste host's layout may have changed", title)))
+}
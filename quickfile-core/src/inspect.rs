@@ -0,0 +1,129 @@
+use std::sync::Arc;
+use futures::future::join_all;
+use reqwest::Client;
+use serde::Serialize;
+use tracing::{info, instrument, warn};
+use crate::backend::unix_now;
+use crate::compression::Codec;
+use crate::encoding::Encoding;
+use crate::error::Error;
+use crate::logging;
+use crate::manifest::{FileEntry, Manifest, Mirrors, StreamInfo};
+use crate::share::download_manifest;
+
+// What a share contains, from its manifest and optionally a check of every part
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ShareInfo {
+    pub link: String,
+    pub version: u32,
+    pub files: Vec<FileInfo>,
+    pub directories: Vec<String>,
+    // Set for shares sent from a stream
+    pub stream: Option<StreamInfo>,
+    // Sum of the known file sizes
+    pub total_size: u64,
+    // Parts are stored as plain encoded data, no manifest version encrypts them
+    pub encrypted: bool,
+    pub created_at: Option<u64>,
+    pub expires_at: Option<u64>,
+    pub expired: bool,
+    // Whether the parts were checked, `missing_parts` is empty otherwise
+    pub probed: bool,
+    pub missing_parts: usize,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FileInfo {
+    pub id: String,
+    pub path: String,
+    // Unknown for manifests from before sizes were recorded
+    pub size: Option<u64>,
+    pub mime_type: String,
+    pub parts: usize,
+    pub parity_parts: usize,
+    pub compression: Codec,
+    pub encoding: Encoding,
+    // Part numbers, starting at 1, that none of the mirrors have any more
+    pub missing_parts: Vec<usize>,
+    // Whether the file can still be rebuilt despite the missing parts
+    pub recoverable: bool,
+}
+
+// Fetches the manifest behind `link`, and checks every part with `probe_parts`
+#[instrument(name = "inspect", skip_all, fields(job = logging::next_job(), link = link))]
+pub async fn inspect(link: &str, probe_parts: bool) -> Result<ShareInfo, Error> {
+    let client = Arc::new(Client::new());
+    let manifest = download_manifest(Arc::clone(&client), link).await?;
+    let mut info = summarize(link.to_string(), &manifest);
+    if probe_parts {
+        probe(&client, &manifest, &mut info).await;
+    }
+    Ok(info)
+}
+
+pub fn summarize(link: String, manifest: &Manifest) -> ShareInfo {
+    let files: Vec<FileInfo> = manifest.files.iter().map(|entry| FileInfo {
+        id: entry.id.clone(),
+        path: entry.path.clone(),
+        size: entry.size,
+        mime_type: mime_guess::from_path(&entry.path).first_or_octet_stream().to_string(),
+        parts: entry.parts.len(),
+        parity_parts: entry.parity.map(|parity| parity.parity_part_count()).unwrap_or(0),
+        compression: entry.compression,
+        encoding: entry.encoding,
+        missing_parts: vec![],
+        recoverable: true,
+    }).collect();
+    ShareInfo {
+        link,
+        version: manifest.version,
+        total_size: files.iter().filter_map(|file| file.size).sum(),
+        files,
+        directories: manifest.directories.clone(),
+        stream: manifest.stream.clone(),
+        encrypted: false,
+        created_at: manifest.created_at,
+        expires_at: manifest.expires_at,
+        expired: manifest.expires_at.is_some_and(|expires_at| expires_at <= unix_now()),
+        probed: false,
+        missing_parts: 0,
+    }
+}
+
+// Checks every part of every file and fills in the missing parts
+pub async fn probe(client: &Client, manifest: &Manifest, info: &mut ShareInfo) {
+    let checks = manifest.files.iter().map(|entry| missing_parts(client, entry));
+    for ((entry, file), missing) in manifest.files.iter().zip(info.files.iter_mut()).zip(join_all(checks).await) {
+        file.recoverable = match &entry.parity {
+            Some(parity) => parity.can_reconstruct(&missing),
+            None => missing.is_empty(),
+        };
+        file.missing_parts = missing.into_iter().map(|index| index + 1).collect();
+    }
+    info.probed = true;
+    info.missing_parts = info.files.iter().map(|file| file.missing_parts.len()).sum();
+}
+
+// Indices of the parts of `entry` that no mirror has
+async fn missing_parts(client: &Client, entry: &FileEntry) -> Vec<usize> {
+    let checks = entry.parts.iter().map(|mirrors| part_exists(client, mirrors));
+    join_all(checks).await.into_iter()
+        .enumerate()
+        .filter(|(_, exists)| !exists)
+        .map(|(index, _)| index)
+        .collect()
+}
+
+// A part exists while one of its mirrors does, unreachable mirrors count as gone
+async fn part_exists(client: &Client, mirrors: &Mirrors) -> bool {
+    for location in &mirrors.0 {
+        match location.exists(client).await {
+            Ok(true) => return true,
+            Ok(false) => info!("Part {} is gone", location),
+            Err(e) => warn!("Failed to check part {}: {}", location, e),
+        }
+    }
+    false
+}
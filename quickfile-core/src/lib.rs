@@ -0,0 +1,18 @@
+// The upload and rebuild pipeline shared by the desktop app, the command line
+// client and the performance harness
+
+pub mod backend;
+pub mod compression;
+pub mod crypto;
+pub mod encoding;
+pub mod error;
+pub mod history;
+pub mod html;
+pub mod inspect;
+pub mod liveness;
+pub mod logging;
+pub mod manifest;
+pub mod parity;
+pub mod settings;
+pub mod share;
+pub mod tree;
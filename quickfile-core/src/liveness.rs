@@ -0,0 +1,205 @@
+use std::sync::Arc;
+use reqwest::Client;
+use tracing::{info, instrument, warn};
+use crate::backend::{Backend, Location, unix_now};
+use crate::error::Error;
+use crate::history::{self, HistoryEntry, ShareStatus};
+use crate::inspect;
+use crate::logging;
+use crate::settings;
+use crate::share::{download_manifest_from, point_entry_at, redirect_json, republish, update_pointer};
+
+// Kept alive shares are uploaded again this long before they expire, or half
+// way through their lifetime when that's shorter
+const REFRESH_MARGIN: u64 = 15 * 60;
+
+// Whether every file of a sent share can still be rebuilt, None when that
+// couldn't be found out
+async fn check_liveness(client: Arc<Client>, entry: &HistoryEntry) -> Option<bool> {
+    let links = entry.links();
+    match download_manifest_from(Arc::clone(&client), &links).await {
+        Ok(manifest) => {
+            let mut info = inspect::summarize(entry.link.clone(), &manifest);
+            inspect::probe(&client, &manifest, &mut info).await;
+            Some(info.files.iter().all(|file| file.recoverable))
+        }
+        // Only count the share as gone when every host says so, a failed
+        // request may just mean being offline
+        Err(e) => {
+            warn!("Liveness check of history entry {} failed: {}", entry.id, e);
+            for link in &links {
+                if !matches!(Location::parse(link).ok()?.exists(&client).await, Ok(false)) {
+                    return None;
+                }
+            }
+            Some(false)
+        }
+    }
+}
+
+// When a kept alive share should be uploaded again, None if it never expires
+fn refresh_at(entry: &HistoryEntry) -> Option<u64> {
+    let expires_at = entry.expires_at?;
+    let uploaded_at = entry.refreshed_at.or(entry.created_at).unwrap_or(expires_at);
+    Some(expires_at - REFRESH_MARGIN.min(expires_at.saturating_sub(uploaded_at) / 2))
+}
+
+// Uploads a kept alive share again, its manifest with the keep alive expiry,
+// and points its history entry, and its pointer if it has one, at the new copy
+#[instrument(name = "refresh", skip_all, fields(job = logging::next_job(), id = %entry.id))]
+async fn refresh_share(client: Arc<Client>, entry: &HistoryEntry) -> Result<(), Error> {
+    let manifest = download_manifest_from(Arc::clone(&client), &entry.links()).await?;
+    let (copy, links) = republish(client, manifest, &entry.backends, true).await?;
+    update_pointer(entry, &links[0])?;
+    history::update(|entries: &mut Vec<HistoryEntry>| {
+        if let Some(stored) = entries.iter_mut().find(|stored| stored.id == entry.id) {
+            point_entry_at(stored, &copy, &links);
+        }
+    })?;
+    info!("Refreshed history entry {}", entry.id);
+    Ok(())
+}
+
+// Uploads again every kept alive share whose refresh is due, meant to be
+// called every minute or so. Shares that turn out to be gone are marked as
+// such and not tried again. Returns how many shares were refreshed.
+pub async fn refresh_due() -> Result<usize, Error> {
+    let client = Arc::new(Client::new());
+    let now = unix_now();
+    let entries: Vec<HistoryEntry> = history::load()?;
+    let mut refreshed = 0;
+    for entry in entries.iter().filter(|entry| entry.keep_alive && entry.alive != Some(false)) {
+        if refresh_at(entry).is_none_or(|refresh_at| refresh_at > now) {
+            continue;
+        }
+        match refresh_share(Arc::clone(&client), entry).await {
+            Ok(()) => refreshed += 1,
+            // Failures past the expiry can't be retried, the parts are gone
+            Err(e) if matches!(e, Error::Expired(_)) || entry.expires_at.is_some_and(|expires_at| expires_at <= unix_now()) => {
+                warn!("Can't keep history entry {} alive any more: {}", entry.id, e);
+                history::update(|entries: &mut Vec<HistoryEntry>| {
+                    if let Some(stored) = entries.iter_mut().find(|stored| stored.id == entry.id) {
+                        stored.alive = Some(false);
+                        stored.checked_at = Some(unix_now());
+                    }
+                })?;
+            }
+            Err(e) => warn!("Failed to keep history entry {} alive: {}", entry.id, e),
+        }
+    }
+    Ok(refreshed)
+}
+
+// Checks every sent share that isn't known to be expired and saves the
+// results, expired entries are removed when the settings ask for it
+#[instrument(name = "check_history", skip_all, fields(job = logging::next_job()))]
+pub async fn check_history() -> Result<(), Error> {
+    let drop_expired = settings::current().drop_expired;
+    let client = Arc::new(Client::new());
+    let now = unix_now();
+    let entries: Vec<HistoryEntry> = history::load()?;
+
+    let mut results = vec![];
+    for entry in entries.iter().filter(|entry| entry.status(now) != ShareStatus::Expired) {
+        if let Some(alive) = check_liveness(Arc::clone(&client), entry).await {
+            results.push((entry.id.clone(), alive));
+        }
+    }
+
+    let dropped = history::update(|entries: &mut Vec<HistoryEntry>| {
+        let now = unix_now();
+        for entry in entries.iter_mut() {
+            if let Some((_, alive)) = results.iter().find(|(id, _)| *id == entry.id) {
+                entry.alive = Some(*alive);
+                entry.checked_at = Some(now);
+            }
+            entry.status = entry.status(now);
+        }
+        let count = entries.len();
+        if drop_expired {
+            entries.retain(|entry| entry.status != ShareStatus::Expired);
+        }
+        count - entries.len()
+    })?;
+    info!("Checked {} shares, {} are gone, dropped {} expired entries", results.len(), results.iter().filter(|(_, alive)| !alive).count(), dropped);
+    Ok(())
+}
+
+// A paste on the first of `backends` that can replace pastes, redirecting
+// to `link`. innomi and paste.rs can't, so a share stored only there has no
+// link that stays the same and gets a new one on every refresh.
+pub(crate) async fn create_pointer(backends: &[Backend], link: &str) -> Result<Option<String>, Error> {
+    let Some(backend) = backends.iter().find(|backend| backend.rewritable()) else {
+        warn!("The share is only stored on backends that can't replace pastes, its link changes on every refresh");
+        return Ok(None);
+    };
+    let pointer_id = backend.post_paste(&Client::new(), redirect_json(link)?, "-1").await?;
+    Ok(Some(Location { backend: backend.clone(), id: pointer_id }.to_string()))
+}
+
+// Turns re-uploading before expiry on or off. Turning it on uploads the share
+// again right away, so its manifest gets the keep alive expiry, which fails
+// once the share is gone. The first time the share also gets a pointer, see
+// `create_pointer`.
+pub async fn set_keep_alive(id: &str, enabled: bool) -> Result<HistoryEntry, Error> {
+    let mut entry = history::find::<HistoryEntry>(id)?;
+    if enabled && !entry.keep_alive {
+        refresh_share(Arc::new(Client::new()), &entry).await.map_err(|e| e.context("The share can't be kept alive"))?;
+        entry = history::find(id)?;
+    }
+    let mut pointer = entry.pointer.clone();
+    if enabled && pointer.is_none() {
+        pointer = create_pointer(&entry.backends, &entry.link).await?;
+    }
+    history::update(|entries: &mut Vec<HistoryEntry>| {
+        entries.iter_mut().find(|stored| stored.id == id).map(|stored| {
+            stored.keep_alive = enabled;
+            stored.pointer = pointer;
+            stored.clone().with_status(unix_now())
+        })
+    })?.ok_or_else(|| Error::invalid(format!("No history entry with id {}", id)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::history::HistoryFile;
+
+    fn entry(created_at: u64, expires_at: u64) -> HistoryEntry {
+        HistoryEntry {
+            id: "1".to_string(),
+            link: "abc".to_string(),
+            mirrors: vec![],
+            files: vec![HistoryFile { path: "a.txt".to_string(), size: None }],
+            created_at: Some(created_at),
+            backends: vec![Backend::Innomi],
+            expire: Some("1h".to_string()),
+            expires_at: Some(expires_at),
+            size: None,
+            stored_size: None,
+            alive: None,
+            checked_at: None,
+            status: ShareStatus::Live,
+            keep_alive: true,
+            pointer: None,
+            refreshed_at: None,
+        }
+    }
+
+    #[test]
+    fn refresh_is_due_a_margin_before_expiry() {
+        assert_eq!(refresh_at(&entry(0, 3600)), Some(3600 - REFRESH_MARGIN));
+    }
+
+    #[test]
+    fn short_lived_shares_are_refreshed_half_way() {
+        assert_eq!(refresh_at(&entry(0, 600)), Some(300));
+        let refreshed = HistoryEntry { refreshed_at: Some(1000), ..entry(0, 1600) };
+        assert_eq!(refresh_at(&refreshed), Some(1300));
+    }
+
+    #[test]
+    fn shares_that_never_expire_are_never_refreshed() {
+        assert_eq!(refresh_at(&HistoryEntry { expires_at: None, ..entry(0, 0) }), None);
+    }
+}
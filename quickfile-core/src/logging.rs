@@ -0,0 +1,259 @@
+// Diagnostics go through tracing into a daily log file in the app data
+// folder, stderr, and a buffer of recent records the app shows in its log panel
+
+use std::collections::VecDeque;
+use std::fs;
+use std::fmt::{self, Display, Write as _};
+use std::io::Write as _;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Mutex, OnceLock, PoisonError};
+use serde::Serialize;
+use time::OffsetDateTime;
+use time::format_description::well_known::Rfc3339;
+use tokio::sync::broadcast;
+use tracing::field::{Field, Visit};
+use tracing::span::{Attributes, Id, Record};
+use tracing::{Event, Subscriber};
+use tracing_appender::non_blocking::{NonBlocking, WorkerGuard};
+use tracing_appender::rolling::{RollingFileAppender, Rotation};
+use tracing_subscriber::layer::{Context, Layer, SubscriberExt};
+use tracing_subscriber::registry::LookupSpan;
+use tracing_subscriber::util::SubscriberInitExt;
+use tracing_subscriber::{EnvFilter, Registry};
+use crate::error::Error;
+use crate::history::history_dir;
+
+pub use tracing::level_filters::LevelFilter;
+
+// Overrides what gets recorded, e.g. QUICKFILE_LOG=debug
+const FILTER_VARIABLE: &str = "QUICKFILE_LOG";
+const DEFAULT_FILTER: &str = "info";
+const KEPT_LOG_FILES: usize = 7;
+const RECENT_RECORDS: usize = 500;
+// Longer values are cut, so no paste or manifest ends up in the log whole
+const MAX_VALUE_LENGTH: usize = 300;
+// Fields whose values give access to a share or hold its contents, they are
+// logged without the value
+const REDACTED_FIELDS: [&str; 6] = ["link", "title", "key", "password", "text", "content"];
+
+#[derive(Serialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct LogRecord {
+    // Milliseconds since the epoch
+    pub at: u64,
+    pub level: String,
+    pub target: String,
+    // The enclosing spans from the outermost, like `send{job=3}:part{index=2}`
+    pub spans: String,
+    pub message: String,
+}
+
+impl Display for LogRecord {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let at = OffsetDateTime::from_unix_timestamp_nanos(self.at as i128 * 1_000_000)
+            .ok()
+            .and_then(|at| at.format(&Rfc3339).ok())
+            .unwrap_or_else(|| self.at.to_string());
+        write!(f, "{} {:>5} ", at, self.level)?;
+        if !self.spans.is_empty() {
+            write!(f, "{}: ", self.spans)?;
+        }
+        write!(f, "{}", self.message)
+    }
+}
+
+struct Recent {
+    records: Mutex<VecDeque<LogRecord>>,
+    sender: broadcast::Sender<LogRecord>,
+}
+
+fn recent_records() -> &'static Recent {
+    static RECENT: OnceLock<Recent> = OnceLock::new();
+    RECENT.get_or_init(|| Recent {
+        records: Mutex::new(VecDeque::with_capacity(RECENT_RECORDS)),
+        sender: broadcast::channel(RECENT_RECORDS).0,
+    })
+}
+
+// The last records, oldest first
+pub fn recent() -> Vec<LogRecord> {
+    recent_records().records.lock().unwrap_or_else(PoisonError::into_inner).iter().cloned().collect()
+}
+
+// Every record from now on, for streaming them to the log panel
+pub fn subscribe() -> broadcast::Receiver<LogRecord> {
+    recent_records().sender.subscribe()
+}
+
+// Numbers jobs so the records of concurrent sends and receives can be told apart
+pub fn next_job() -> u64 {
+    static JOBS: AtomicU64 = AtomicU64::new(1);
+    JOBS.fetch_add(1, Ordering::Relaxed)
+}
+
+pub fn log_dir() -> Result<PathBuf, Error> {
+    Ok(history_dir()?.join("logs"))
+}
+
+// Installs the subscriber. Records at `stderr_level` or above are also printed
+// to stderr. Keep the guard alive for as long as the log file should be written.
+pub fn init(stderr_level: LevelFilter) -> Option<WorkerGuard> {
+    let (file, guard) = match open_log_file() {
+        Ok((file, guard)) => (Some(file), Some(guard)),
+        Err(e) => {
+            eprintln!("Logging to stderr only: {}", e);
+            (None, None)
+        }
+    };
+    let filter = EnvFilter::try_from_env(FILTER_VARIABLE).unwrap_or_else(|_| EnvFilter::new(DEFAULT_FILTER));
+    let records = Records { file, stderr_level };
+    // Fails when a subscriber is already set, which then keeps logging
+    let _ = Registry::default().with(filter).with(records).try_init();
+    guard
+}
+
+fn open_log_file() -> Result<(NonBlocking, WorkerGuard), Error> {
+    let dir = log_dir()?;
+    fs::create_dir_all(&dir).map_err(|e| Error::io(format!("Failed to create {}", dir.display()), e))?;
+    let appender = RollingFileAppender::builder()
+        .rotation(Rotation::DAILY)
+        .filename_prefix("quickfile")
+        .filename_suffix("log")
+        .max_log_files(KEPT_LOG_FILES)
+        .build(&dir)
+        .map_err(|e| Error::Io(format!("Failed to open the log in {}: {}", dir.display(), e)))?;
+    Ok(tracing_appender::non_blocking(appender))
+}
+
+fn truncate(mut text: String) -> String {
+    if let Some((cut, _)) = text.char_indices().nth(MAX_VALUE_LENGTH) {
+        let more = text[cut..].chars().count();
+        text.truncate(cut);
+        let _ = write!(text, "… ({} more characters)", more);
+    }
+    text
+}
+
+// The message and the other fields of an event or span
+#[derive(Default)]
+struct Fields {
+    message: String,
+    rest: String,
+}
+
+impl Fields {
+    fn add(&mut self, field: &Field, value: String) {
+        let value = if REDACTED_FIELDS.contains(&field.name()) { "<redacted>".to_string() } else { truncate(value) };
+        if field.name() == "message" {
+            self.message = value;
+        } else {
+            if !self.rest.is_empty() {
+                self.rest.push(' ');
+            }
+            let _ = write!(self.rest, "{}={}", field.name(), value);
+        }
+    }
+}
+
+impl Visit for Fields {
+    fn record_str(&mut self, field: &Field, value: &str) {
+        self.add(field, value.to_string());
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        self.add(field, format!("{:?}", value));
+    }
+}
+
+struct Records {
+    file: Option<NonBlocking>,
+    stderr_level: LevelFilter,
+}
+
+impl<S: Subscriber + for<'a> LookupSpan<'a>> Layer<S> for Records {
+    fn on_new_span(&self, attributes: &Attributes<'_>, id: &Id, ctx: Context<'_, S>) {
+        let mut fields = Fields::default();
+        attributes.record(&mut fields);
+        if let Some(span) = ctx.span(id) {
+            span.extensions_mut().insert(fields);
+        }
+    }
+
+    fn on_record(&self, id: &Id, values: &Record<'_>, ctx: Context<'_, S>) {
+        if let Some(span) = ctx.span(id) {
+            if let Some(fields) = span.extensions_mut().get_mut::<Fields>() {
+                values.record(fields);
+            }
+        }
+    }
+
+    fn on_event(&self, event: &Event<'_>, ctx: Context<'_, S>) {
+        let mut fields = Fields::default();
+        event.record(&mut fields);
+        let spans = ctx.event_scope(event).map(|scope| {
+            scope.from_root().map(|span| match span.extensions().get::<Fields>() {
+                Some(fields) if !fields.rest.is_empty() => format!("{}{{{}}}", span.name(), fields.rest),
+                _ => span.name().to_string(),
+            }).collect::<Vec<String>>().join(":")
+        }).unwrap_or_default();
+        let message = match fields.rest.is_empty() {
+            true => fields.message,
+            false => format!("{} {}", fields.message, fields.rest),
+        };
+        let metadata = event.metadata();
+        let record = LogRecord {
+            at: (OffsetDateTime::now_utc().unix_timestamp_nanos() / 1_000_000) as u64,
+            level: metadata.level().to_string(),
+            target: metadata.target().to_string(),
+            spans,
+            message,
+        };
+
+        if self.stderr_level >= *metadata.level() {
+            eprintln!("{}", record);
+        }
+        if let Some(file) = &self.file {
+            let _ = writeln!(file.clone(), "{}", record);
+        }
+        let recent = recent_records();
+        let mut records = recent.records.lock().unwrap_or_else(PoisonError::into_inner);
+        if records.len() == RECENT_RECORDS {
+            records.pop_front();
+        }
+        records.push_back(record.clone());
+        drop(records);
+        // Fails only when nobody is listening
+        let _ = recent.sender.send(record);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn short_values_are_kept_as_they_are() {
+        let message = "The page has no div#code, the paste host's layout may have changed";
+        assert_eq!(truncate(message.to_string()), message);
+    }
+
+    #[test]
+    fn long_values_are_cut() {
+        let cut = truncate("é".repeat(MAX_VALUE_LENGTH + 5));
+        assert_eq!(cut, format!("{}… (5 more characters)", "é".repeat(MAX_VALUE_LENGTH)));
+    }
+
+    #[test]
+    fn share_links_and_contents_are_redacted() {
+        let subscriber = Registry::default().with(Records { file: None, stderr_level: LevelFilter::OFF });
+        tracing::subscriber::with_default(subscriber, || {
+            let span = tracing::info_span!("receive", job = 7, link = "8n8269485jobpqq37hep76hx");
+            let _entered = span.enter();
+            tracing::info!(text = "PGRyYWZ0PiAmIGZpbmFsPwo=", path = "notes/a.txt", "Redaction test");
+        });
+        let record = recent().into_iter().rev().find(|record| record.message.starts_with("Redaction test")).unwrap();
+        assert_eq!(record.spans, "receive{job=7 link=<redacted>}");
+        assert_eq!(record.message, "Redaction test text=<redacted> path=notes/a.txt");
+    }
+}
@@ -0,0 +1,291 @@
+use std::collections::HashMap;
+use serde::{Serialize, Deserialize, Deserializer};
+use crate::backend::{Backend, Location};
+use crate::compression::Codec;
+use crate::encoding::Encoding;
+use crate::error::Error;
+use crate::parity::Parity;
+
+pub const MANIFEST_VERSION: u32 = 4;
+
+// The manifest (file_data.json) is what a share title points at. It lists the
+// locations of every part of every file, plus how to turn them back into
+// the original bytes.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Manifest {
+    pub version: u32,
+    // In the order the files were picked
+    pub files: Vec<FileEntry>,
+    // Empty directories, everything else is implied by the file paths
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub directories: Vec<String>,
+    // Upload time in seconds since the Unix epoch, unknown for older manifests
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub created_at: Option<u64>,
+    // When the first paste of the share expires, None if it never does or,
+    // without `created_at`, if that isn't known
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expires_at: Option<u64>,
+    // Set when the share is a single piped stream rather than picked files
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub stream: Option<StreamInfo>,
+}
+
+// What's known about a piped upload, its one file entry holds the data
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct StreamInfo {
+    // Given by the sender, e.g. "backup.tar"
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    // Bytes read, only known once the stream has ended
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub length: Option<u64>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct FileEntry {
+    // Unique within the manifest, unlike the path
+    #[serde(default)]
+    pub id: String,
+    // Relative to the share root, `/` separated
+    #[serde(default)]
+    pub path: String,
+    // Original size in bytes, missing in manifests before version 4
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub size: Option<u64>,
+    #[serde(default)]
+    pub compression: Codec,
+    #[serde(default)]
+    pub encoding: Encoding,
+    // Erasure coding layout when the parts include parity parts
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub parity: Option<Parity>,
+    // Where each part is stored, in order
+    pub parts: Vec<Mirrors>,
+}
+
+// Every copy of a part, tried in order on download. Version 2 manifests
+// store just the innomi title.
+#[derive(Serialize, Debug, Clone)]
+#[serde(transparent)]
+pub struct Mirrors(pub Vec<Location>);
+
+impl<'de> Deserialize<'de> for Mirrors {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum RawMirrors {
+            Title(String),
+            Locations(Vec<Location>),
+        }
+        Ok(match RawMirrors::deserialize(deserializer)? {
+            RawMirrors::Title(id) => Mirrors(vec![Location { backend: Backend::Innomi, id }]),
+            RawMirrors::Locations(locations) => Mirrors(locations),
+        })
+    }
+}
+
+// Stored instead of a manifest where a share's link has to keep working after
+// it's uploaded again, points at the current manifest
+#[derive(Serialize, Deserialize)]
+pub struct Redirect {
+    pub redirect: String,
+}
+
+pub fn redirect_target(json: &str) -> Option<String> {
+    serde_json::from_str::<Redirect>(json).ok().map(|redirect| redirect.redirect)
+}
+
+// Shapes a manifest can have on the paste host
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum RawManifest {
+    // Version 4 and later
+    Listed {
+        version: u32,
+        files: Vec<FileEntry>,
+        #[serde(default)]
+        directories: Vec<String>,
+        #[serde(default)]
+        created_at: Option<u64>,
+        #[serde(default)]
+        expires_at: Option<u64>,
+        #[serde(default)]
+        stream: Option<StreamInfo>,
+    },
+    // Versions 2 and 3: { "version": 3, "files": { "<path>": { ... } } }
+    Keyed {
+        version: u32,
+        files: HashMap<String, FileEntry>,
+        #[serde(default)]
+        directories: Vec<String>,
+    },
+    // Version 1: { "<filename>": [{ "part-1": "<title>" }, ...] }
+    Legacy(HashMap<String, Vec<HashMap<String, String>>>),
+}
+
+impl Default for Manifest {
+    fn default() -> Self {
+        Manifest { version: MANIFEST_VERSION, files: vec![], directories: vec![], created_at: None, expires_at: None, stream: None }
+    }
+}
+
+pub fn new_file_id() -> Result<String, Error> {
+    let mut id = [0u8; 8];
+    openssl::rand::rand_bytes(&mut id)?;
+    Ok(hex::encode(id))
+}
+
+// Older manifests are keyed by path and have no order, sort them by path and
+// number the files to get stable ids
+fn from_keyed(version: u32, files: HashMap<String, FileEntry>, directories: Vec<String>) -> Manifest {
+    let mut files: Vec<(String, FileEntry)> = files.into_iter().collect();
+    files.sort_by(|a, b| a.0.cmp(&b.0));
+    let files = files.into_iter().enumerate().map(|(index, (path, mut entry))| {
+        entry.id = (index + 1).to_string();
+        entry.path = path;
+        entry
+    }).collect();
+    Manifest { version, files, directories, created_at: None, expires_at: None, stream: None }
+}
+
+impl Manifest {
+    pub fn parse(json: &str) -> Result<Self, Error> {
+        let raw: RawManifest = serde_json::from_str(json).map_err(|e| Error::Decode(format!("Failed to parse manifest: {}", e)))?;
+        let manifest = match raw {
+            RawManifest::Listed { version, files, directories, created_at, expires_at, stream } => {
+                if version > MANIFEST_VERSION {
+                    return Err(Error::Decode(format!("Manifest version {} is newer than supported version {}", version, MANIFEST_VERSION)));
+                }
+                Manifest { version, files, directories, created_at, expires_at, stream }
+            }
+            RawManifest::Keyed { version, files, directories } => from_keyed(version, files, directories),
+            RawManifest::Legacy(files) => {
+                let files = files.into_iter().map(|(filename, part_maps)| {
+                    let mut parts: Vec<(usize, String)> = part_maps.into_iter()
+                        .flat_map(|part_map| part_map.into_iter())
+                        .enumerate()
+                        .map(|(position, (part_name, title))| {
+                            let index = part_name.strip_prefix("part-")
+                                .and_then(|n| n.parse::<usize>().ok())
+                                .unwrap_or(position + 1);
+                            (index, title)
+                        })
+                        .collect();
+                    parts.sort_by_key(|k| k.0);
+                    let entry = FileEntry {
+                        id: String::new(),
+                        path: String::new(),
+                        size: None,
+                        compression: Codec::None,
+                        encoding: Encoding::Base64,
+                        parity: None,
+                        parts: parts.into_iter()
+                            .map(|(_, id)| Mirrors(vec![Location { backend: Backend::Innomi, id }]))
+                            .collect(),
+                    };
+                    (filename, entry)
+                }).collect();
+                from_keyed(1, files, vec![])
+            }
+        };
+        manifest.check()?;
+        Ok(manifest)
+    }
+
+    // Manifests come from the paste host, so check what the rebuild relies on
+    fn check(&self) -> Result<(), Error> {
+        for entry in &self.files {
+            for location in entry.parts.iter().flat_map(|mirrors| &mirrors.0) {
+                location.check_id().map_err(|e| Error::Refused(format!("{}: {}", entry.path, e)))?;
+            }
+            if let Some(parity) = &entry.parity {
+                parity.check(entry.parts.len()).map_err(|e| e.context(&entry.path))?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn with_parity(parity: &str) -> String {
+        format!(r#"{{ "version": 4, "files": [{{ "id": "1", "path": "a.bin", "parity": {}, "parts": [] }}] }}"#, parity)
+    }
+
+    #[test]
+    fn parse_refuses_parity_layouts_that_would_panic() {
+        for parity in [
+            r#"{ "data_shards": 0, "parity_shards": 1, "shard_size": 64, "length": 10 }"#,
+            r#"{ "data_shards": 1, "parity_shards": 1, "shard_size": 0, "length": 10 }"#,
+            r#"{ "data_shards": 1, "parity_shards": 1, "shard_size": 64, "length": 18446744073709551615 }"#,
+        ] {
+            assert!(matches!(Manifest::parse(&with_parity(parity)), Err(Error::Decode(_))), "{}", parity);
+        }
+    }
+
+    #[test]
+    fn parse_refuses_part_ids_that_are_paths() {
+        for id in ["../secret.txt", "/etc/passwd", "a\\b", "C:x", ".."] {
+            let json = format!(r#"{{ "version": 4, "files": [{{ "id": "1", "path": "a.bin", "parts": [[{{ "backend": {{ "local": "/tmp" }}, "id": {:?} }}]] }}] }}"#, id);
+            assert!(matches!(Manifest::parse(&json), Err(Error::Refused(_))), "{}", id);
+        }
+        let json = r#"{ "version": 4, "files": [{ "id": "1", "path": "a.bin", "parts": [[{ "backend": { "local": "/tmp" }, "id": "0a1b.txt" }]] }] }"#;
+        assert!(Manifest::parse(json).is_ok());
+    }
+
+    fn innomi_ids(entry: &FileEntry) -> Vec<&str> {
+        entry.parts.iter().map(|mirrors| {
+            assert_eq!(mirrors.0.len(), 1);
+            assert_eq!(mirrors.0[0].backend, Backend::Innomi);
+            mirrors.0[0].id.as_str()
+        }).collect()
+    }
+
+    #[test]
+    fn parse_reads_version_1_manifests() {
+        // As the first app wrote file_data.json, parts out of order on purpose
+        let json = r#"{
+  "test - Copy.jpg": [
+    { "part-2": "tseu454ue4wp6xxv6upt6vq3" },
+    { "part-1": "2ojjs4sqdxaker4pxraddr2v" }
+  ],
+  "notes.txt": [{ "part-1": "ed9ytgosdrfrvd3ytm5o9jrc" }]
+}"#;
+        let manifest = Manifest::parse(json).unwrap();
+        assert_eq!(manifest.version, 1);
+        let files: Vec<(&str, &str)> = manifest.files.iter().map(|entry| (entry.id.as_str(), entry.path.as_str())).collect();
+        assert_eq!(files, [("1", "notes.txt"), ("2", "test - Copy.jpg")]);
+        assert_eq!(innomi_ids(&manifest.files[1]), ["2ojjs4sqdxaker4pxraddr2v", "tseu454ue4wp6xxv6upt6vq3"]);
+        assert_eq!(manifest.files[1].encoding, Encoding::Base64);
+        assert_eq!(manifest.files[1].compression, Codec::None);
+        assert_eq!(manifest.files[1].size, None);
+    }
+
+    #[test]
+    fn parse_reads_keyed_manifests_with_bare_titles() {
+        let json = r#"{
+  "version": 2,
+  "files": {
+    "b.txt": { "parts": ["86zbybqkemxgxgf9cwj4wpp2"] },
+    "a/c.txt": { "encoding": "z85", "parts": ["8n8269485jobpqq37hep76hx", "z6uovtcjxfpsdxrczxrztmmv"] }
+  },
+  "directories": ["empty"]
+}"#;
+        let manifest = Manifest::parse(json).unwrap();
+        assert_eq!(manifest.version, 2);
+        assert_eq!(manifest.files[0].path, "a/c.txt");
+        assert_eq!(manifest.files[0].encoding, Encoding::Z85);
+        assert_eq!(innomi_ids(&manifest.files[0]), ["8n8269485jobpqq37hep76hx", "z6uovtcjxfpsdxrczxrztmmv"]);
+        assert_eq!(manifest.files[1].id, "2");
+        assert_eq!(manifest.directories, ["empty"]);
+        assert_eq!(manifest.created_at, None);
+    }
+
+    #[test]
+    fn parse_refuses_manifests_from_newer_versions() {
+        assert!(matches!(Manifest::parse(r#"{ "version": 5, "files": [] }"#), Err(Error::Decode(_))));
+    }
+}
@@ -0,0 +1,344 @@
+use std::ops::Range;
+use serde::{Serialize, Deserialize};
+use reed_solomon_erasure::galois_8::ReedSolomon;
+use crate::error::Error;
+
+// Reed-Solomon over GF(2^8) can't have more than 256 shards per stripe
+const MAX_SHARDS: usize = 256;
+// Larger than any part a backend takes, in any encoding
+const MAX_SHARD_SIZE: usize = 32 * 1024 * 1024;
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct ParityOptions {
+    // Data parts per stripe
+    pub data_shards: usize,
+    // Parity parts added to every stripe, each stripe survives losing this many parts
+    pub parity_shards: usize,
+}
+
+// Erasure coding layout recorded in the manifest. Parts are stored stripe by
+// stripe, the data shards of a stripe followed by its parity shards. The last
+// stripe can have fewer data shards, the last data shard is zero padded.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug)]
+pub struct Parity {
+    pub data_shards: usize,
+    pub parity_shards: usize,
+    pub shard_size: usize,
+    // Length of the data before padding
+    pub length: usize,
+}
+
+// Outcome of putting a file back together from its parts
+#[derive(Serialize, Clone, Copy, Debug, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct Recovery {
+    pub lost_parts: usize,
+    pub parity_parts: usize,
+    // Most parts lost in a single stripe, against `parity_shards` tolerated
+    pub worst_stripe_lost: usize,
+}
+
+impl Recovery {
+    pub fn add_stripe(&mut self, lost: usize) {
+        self.lost_parts += lost;
+        self.worst_stripe_lost = self.worst_stripe_lost.max(lost);
+    }
+}
+
+impl Parity {
+    // Data shard count of every stripe
+    fn stripes(&self) -> Vec<usize> {
+        let total = self.length.div_ceil(self.shard_size).max(1);
+        (0..total).step_by(self.data_shards)
+            .map(|start| (total - start).min(self.data_shards))
+            .collect()
+    }
+
+    // Checks a layout read from a manifest that lists `parts` parts. A crafted
+    // one could otherwise divide by zero or size buffers from made up numbers.
+    pub fn check(&self, parts: usize) -> Result<(), Error> {
+        let invalid = |reason: &str| Err(Error::Decode(format!("Invalid parity layout: {}", reason)));
+        if self.data_shards == 0 || self.parity_shards == 0 {
+            return invalid("every stripe needs at least one data and one parity part");
+        }
+        if self.data_shards.saturating_add(self.parity_shards) > MAX_SHARDS {
+            return invalid(&format!("a stripe can have at most {} data and parity parts", MAX_SHARDS));
+        }
+        if self.shard_size == 0 || self.shard_size > MAX_SHARD_SIZE {
+            return invalid(&format!("parts have to hold between 1 and {} bytes", MAX_SHARD_SIZE));
+        }
+        // Bounds the number of stripes by the number of parts
+        if self.shard_size.checked_mul(parts).is_none_or(|capacity| self.length > capacity) {
+            return invalid(&format!("{} bytes don't fit in {} parts", self.length, parts));
+        }
+        if self.part_count() != parts {
+            return invalid(&format!("expected {} parts, the manifest lists {}", self.part_count(), parts));
+        }
+        Ok(())
+    }
+
+    // Indexes of the parts of every stripe, in upload order
+    pub fn stripe_parts(&self) -> Vec<Range<usize>> {
+        let mut start = 0;
+        self.stripes().into_iter().map(|data_shards| {
+            let parts = start..start + data_shards + self.parity_shards;
+            start = parts.end;
+            parts
+        }).collect()
+    }
+
+    pub fn part_count(&self) -> usize {
+        self.stripes().iter().map(|data| data + self.parity_shards).sum()
+    }
+
+    pub fn parity_part_count(&self) -> usize {
+        self.stripes().len() * self.parity_shards
+    }
+
+    // Whether the data survives losing the parts at `lost` (0 based, upload order)
+    pub fn can_reconstruct(&self, lost: &[usize]) -> bool {
+        let mut start = 0;
+        self.stripes().into_iter().all(|data_shards| {
+            let end = start + data_shards + self.parity_shards;
+            let stripe_lost = lost.iter().filter(|&&index| (start..end).contains(&index)).count();
+            start = end;
+            stripe_lost <= self.parity_shards
+        })
+    }
+}
+
+// Splits `data` into shards of `shard_size` bytes and appends parity shards
+// to every stripe. Returns the layout and the shards in upload order.
+pub fn encode(data: &[u8], shard_size: usize, options: ParityOptions) -> Result<(Parity, Vec<Vec<u8>>), Error> {
+    check_options(options)?;
+    // Data smaller than a stripe is spread over its data parts rather than
+    // padded up to the chunk size
+    let shard_size = shard_size.min(data.len().div_ceil(options.data_shards)).max(1);
+    let parity = layout(options, shard_size, data.len());
+    let shards = encode_stripes(data, &parity)?;
+    Ok((parity, shards))
+}
+
+fn check_options(options: ParityOptions) -> Result<(), Error> {
+    if options.data_shards == 0 || options.parity_shards == 0 {
+        return Err(Error::invalid("Parity needs at least one data and one parity part per stripe"));
+    }
+    if options.data_shards + options.parity_shards > MAX_SHARDS {
+        return Err(Error::invalid(format!("A stripe can have at most {} data and parity parts", MAX_SHARDS)));
+    }
+    Ok(())
+}
+
+fn layout(options: ParityOptions, shard_size: usize, length: usize) -> Parity {
+    Parity { data_shards: options.data_shards, parity_shards: options.parity_shards, shard_size, length }
+}
+
+fn encode_stripes(data: &[u8], parity: &Parity) -> Result<Vec<Vec<u8>>, Error> {
+    let mut chunks = data.chunks(parity.shard_size);
+    let mut shards = Vec::with_capacity(parity.part_count());
+    for data_shards in parity.stripes() {
+        let rs = ReedSolomon::new(data_shards, parity.parity_shards).map_err(|e| Error::Internal(format!("{:?}", e)))?;
+        let mut stripe: Vec<Vec<u8>> = (0..data_shards + parity.parity_shards).map(|i| {
+            let mut shard = if i < data_shards { chunks.next().unwrap_or(&[]).to_vec() } else { Vec::new() };
+            shard.resize(parity.shard_size, 0);
+            shard
+        }).collect();
+        rs.encode(&mut stripe).map_err(|e| Error::Internal(format!("Parity encoding failed: {:?}", e)))?;
+        shards.extend(stripe);
+    }
+    Ok(shards)
+}
+
+// Encodes data fed in pieces one stripe at a time, so a stream or a large
+// file never has to be held in memory whole. Gives the same layout and
+// shards as `encode`.
+pub struct Encoder {
+    options: ParityOptions,
+    shard_size: usize,
+    // Data of the stripe being filled
+    buffer: Vec<u8>,
+    length: usize,
+}
+
+impl Encoder {
+    pub fn new(shard_size: usize, options: ParityOptions) -> Result<Self, Error> {
+        check_options(options)?;
+        Ok(Encoder { options, shard_size: shard_size.max(1), buffer: Vec::new(), length: 0 })
+    }
+
+    fn stripe_size(&self) -> usize {
+        self.shard_size * self.options.data_shards
+    }
+
+    // Takes more data, returns the shards of every stripe it completed
+    pub fn write(&mut self, data: &[u8]) -> Result<Vec<Vec<u8>>, Error> {
+        self.buffer.extend_from_slice(data);
+        let mut shards = vec![];
+        // Keep the last full stripe until more data comes, if it's the only
+        // one `finish` may still spread it over smaller shards
+        while self.buffer.len() > self.stripe_size() {
+            let stripe: Vec<u8> = self.buffer.drain(..self.stripe_size()).collect();
+            shards.extend(encode_stripes(&stripe, &layout(self.options, self.shard_size, stripe.len()))?);
+            self.length += stripe.len();
+        }
+        Ok(shards)
+    }
+
+    // Returns the layout and the shards of the last stripe
+    pub fn finish(self) -> Result<(Parity, Vec<Vec<u8>>), Error> {
+        if self.length == 0 {
+            return encode(&self.buffer, self.shard_size, self.options);
+        }
+        let shards = encode_stripes(&self.buffer, &layout(self.options, self.shard_size, self.buffer.len()))?;
+        Ok((layout(self.options, self.shard_size, self.length + self.buffer.len()), shards))
+    }
+}
+
+// Rebuilds the data from shards in upload order, `None` for parts that
+// couldn't be downloaded
+pub fn reconstruct(parity: &Parity, mut shards: Vec<Option<Vec<u8>>>) -> Result<(Vec<u8>, Recovery), Error> {
+    if shards.len() != parity.part_count() {
+        return Err(Error::Decode(format!("Expected {} parts, the manifest lists {}", parity.part_count(), shards.len())));
+    }
+
+    let mut recovery = Recovery {
+        parity_parts: parity.parity_part_count(),
+        ..Recovery::default()
+    };
+    let mut data = vec![];
+    for (stripe_index, parts) in parity.stripe_parts().into_iter().enumerate() {
+        let (stripe_data, lost) = reconstruct_stripe(parity, stripe_index, &mut shards[parts])?;
+        recovery.add_stripe(lost);
+        data.extend(stripe_data);
+    }
+    data.truncate(parity.length);
+    Ok((data, recovery))
+}
+
+// Rebuilds the data shards of stripe `stripe_index` from its parts, `None`
+// for lost ones. Returns them joined, padding included, and how many parts
+// were lost.
+pub fn reconstruct_stripe(parity: &Parity, stripe_index: usize, stripe: &mut [Option<Vec<u8>>]) -> Result<(Vec<u8>, usize), Error> {
+    let data_shards = stripe.len().saturating_sub(parity.parity_shards);
+    let lost = stripe.iter().filter(|shard| shard.is_none()).count();
+    if lost > parity.parity_shards {
+        return Err(Error::Decode(format!("Stripe {} lost {} parts but only has {} parity parts", stripe_index + 1, lost, parity.parity_shards)));
+    }
+    if stripe.iter().flatten().any(|shard| shard.len() != parity.shard_size) {
+        return Err(Error::Decode(format!("Stripe {} has a part of the wrong size", stripe_index + 1)));
+    }
+    if lost > 0 {
+        let rs = ReedSolomon::new(data_shards, parity.parity_shards).map_err(|e| Error::Internal(format!("{:?}", e)))?;
+        rs.reconstruct_data(stripe).map_err(|e| Error::Decode(format!("Parity reconstruction failed: {:?}", e)))?;
+    }
+    Ok((stripe.iter_mut().take(data_shards).flat_map(|shard| shard.take().unwrap_or_default()).collect(), lost))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const OPTIONS: ParityOptions = ParityOptions { data_shards: 3, parity_shards: 2 };
+
+    fn data(length: usize) -> Vec<u8> {
+        (0..length).map(|i| (i * 7 % 251) as u8).collect()
+    }
+
+    #[test]
+    fn encode_lays_out_stripes_with_a_short_last_one() {
+        // 10 bytes in 2 byte shards: stripes of 3 and 2 data shards
+        let (parity, shards) = encode(&data(10), 2, OPTIONS).unwrap();
+        assert_eq!(parity.part_count(), 9);
+        assert_eq!(parity.parity_part_count(), 4);
+        assert_eq!(shards.len(), 9);
+        assert!(shards.iter().all(|shard| shard.len() == 2));
+    }
+
+    #[test]
+    fn encode_spreads_small_data_over_the_stripe() {
+        let (parity, shards) = encode(&data(10), 1024, OPTIONS).unwrap();
+        assert_eq!(parity.shard_size, 4);
+        assert_eq!(parity.part_count(), 5);
+        assert!(shards.iter().all(|shard| shard.len() == 4));
+        let (rebuilt, _) = reconstruct(&parity, shards.into_iter().map(Some).collect()).unwrap();
+        assert_eq!(rebuilt, data(10));
+    }
+
+    #[test]
+    fn encoding_in_pieces_gives_the_same_shards() {
+        // Short of a stripe, exactly one, two and a bit
+        for length in [0, 5, 24, 50] {
+            let (parity, shards) = encode(&data(length), 8, OPTIONS).unwrap();
+            for piece in [1, 7, 100] {
+                let mut encoder = Encoder::new(8, OPTIONS).unwrap();
+                let mut streamed = vec![];
+                for chunk in data(length).chunks(piece) {
+                    streamed.extend(encoder.write(chunk).unwrap());
+                }
+                let (streamed_parity, last_shards) = encoder.finish().unwrap();
+                streamed.extend(last_shards);
+                assert_eq!((streamed_parity.shard_size, streamed_parity.length), (parity.shard_size, parity.length), "{} in {}", length, piece);
+                assert_eq!(streamed, shards, "{} in {}", length, piece);
+            }
+        }
+    }
+
+    #[test]
+    fn reconstruct_without_losses_gives_back_the_data() {
+        let (parity, shards) = encode(&data(1000), 64, OPTIONS).unwrap();
+        let (rebuilt, recovery) = reconstruct(&parity, shards.into_iter().map(Some).collect()).unwrap();
+        assert_eq!(rebuilt, data(1000));
+        assert_eq!(recovery.lost_parts, 0);
+    }
+
+    #[test]
+    fn reconstruct_recovers_as_many_lost_parts_as_parity_parts_per_stripe() {
+        let (parity, shards) = encode(&data(1000), 64, OPTIONS).unwrap();
+        // Lose two parts of every stripe, data and parity alike
+        let lost: Vec<usize> = (0..parity.part_count()).filter(|index| index % 5 < 2).collect();
+        assert!(parity.can_reconstruct(&lost));
+        let shards = shards.into_iter().enumerate()
+            .map(|(index, shard)| if lost.contains(&index) { None } else { Some(shard) })
+            .collect();
+        let (rebuilt, recovery) = reconstruct(&parity, shards).unwrap();
+        assert_eq!(rebuilt, data(1000));
+        assert_eq!(recovery.lost_parts, lost.len());
+        assert_eq!(recovery.worst_stripe_lost, 2);
+    }
+
+    #[test]
+    fn reconstruct_fails_past_the_limit() {
+        let (parity, shards) = encode(&data(1000), 64, OPTIONS).unwrap();
+        let lost = [0, 1, 2];
+        assert!(!parity.can_reconstruct(&lost));
+        let shards = shards.into_iter().enumerate()
+            .map(|(index, shard)| if lost.contains(&index) { None } else { Some(shard) })
+            .collect();
+        assert!(matches!(reconstruct(&parity, shards), Err(Error::Decode(_))));
+    }
+
+    #[test]
+    fn check_accepts_what_encode_produces() {
+        let (parity, shards) = encode(&data(1000), 64, OPTIONS).unwrap();
+        parity.check(shards.len()).unwrap();
+    }
+
+    #[test]
+    fn check_refuses_crafted_layouts() {
+        let valid = Parity { data_shards: 3, parity_shards: 2, shard_size: 64, length: 1000 };
+        let crafted = [
+            Parity { data_shards: 0, ..valid },
+            Parity { parity_shards: 0, ..valid },
+            Parity { data_shards: 200, parity_shards: 100, ..valid },
+            Parity { parity_shards: usize::MAX, ..valid },
+            Parity { shard_size: 0, ..valid },
+            Parity { shard_size: usize::MAX, ..valid },
+            Parity { length: usize::MAX, ..valid },
+        ];
+        for parity in crafted {
+            assert!(matches!(parity.check(valid.part_count()), Err(Error::Decode(_))), "{:?}", parity);
+        }
+        assert!(matches!(valid.check(valid.part_count() - 1), Err(Error::Decode(_))));
+    }
+}
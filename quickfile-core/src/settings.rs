@@ -0,0 +1,186 @@
+// Settings shared by the app and the command line client, kept as JSON in the
+// app config folder. Missing fields take their defaults, so older files keep
+// working as settings are added.
+
+use std::fs;
+use std::path::PathBuf;
+use std::sync::{Mutex, OnceLock, PoisonError};
+use serde::{Serialize, Deserialize};
+use tracing::{info, warn};
+use crate::backend::EXPIRE_VALUES;
+use crate::error::Error;
+use crate::history::APP_IDENTIFIER;
+use crate::tree;
+
+pub const SETTINGS_VERSION: u32 = 1;
+
+const SETTINGS_FILE: &str = "settings.json";
+// Bounds for the values users can enter
+const MIN_CHUNK_SIZE: usize = 1024;
+const MAX_PARALLEL_TRANSFERS: usize = 64;
+// Replaces the app config folder, see `use_settings_dir`
+static SETTINGS_DIR: OnceLock<PathBuf> = OnceLock::new();
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(rename_all = "camelCase", default)]
+pub struct Settings {
+    pub version: u32,
+    // How long the paste host keeps parts and manifests, as innomi expire values
+    pub part_expire: String,
+    pub manifest_expire: String,
+    // Caps the characters per part below what the backends accept, None to
+    // use as much as they take
+    pub max_chunk_size: Option<usize>,
+    // Parts uploaded or downloaded at the same time for one file
+    pub max_parallel_transfers: usize,
+    // Where the app saves received files, the Downloads folder when None
+    pub download_dir: Option<PathBuf>,
+    pub endpoints: Endpoints,
+    // Whether the liveness check removes expired shares from the history
+    pub drop_expired: bool,
+}
+
+// Base addresses of the paste hosts, e.g. to go through a mirror
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(rename_all = "camelCase", default)]
+pub struct Endpoints {
+    pub innomi: String,
+    pub paste_rs: String,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Settings {
+            version: SETTINGS_VERSION,
+            part_expire: "1h".to_string(),
+            manifest_expire: "10m".to_string(),
+            max_chunk_size: None,
+            max_parallel_transfers: 8,
+            download_dir: None,
+            endpoints: Endpoints::default(),
+            drop_expired: false,
+        }
+    }
+}
+
+impl Default for Endpoints {
+    fn default() -> Self {
+        Endpoints {
+            innomi: "https://pst.innomi.net".to_string(),
+            paste_rs: "https://paste.rs".to_string(),
+        }
+    }
+}
+
+impl Settings {
+    pub fn validate(&self) -> Result<(), Error> {
+        if self.version > SETTINGS_VERSION {
+            return Err(Error::Decode(format!("Settings version {} is newer than supported version {}", self.version, SETTINGS_VERSION)));
+        }
+        for (name, expire) in [("partExpire", &self.part_expire), ("manifestExpire", &self.manifest_expire)] {
+            if !EXPIRE_VALUES.contains(&expire.as_str()) {
+                return Err(Error::invalid(format!("{} should be one of {}, not {:?}", name, EXPIRE_VALUES.join(", "), expire)));
+            }
+        }
+        if self.max_chunk_size.is_some_and(|size| size < MIN_CHUNK_SIZE) {
+            return Err(Error::invalid(format!("maxChunkSize should be at least {} characters", MIN_CHUNK_SIZE)));
+        }
+        if !(1..=MAX_PARALLEL_TRANSFERS).contains(&self.max_parallel_transfers) {
+            return Err(Error::invalid(format!("maxParallelTransfers should be between 1 and {}", MAX_PARALLEL_TRANSFERS)));
+        }
+        if self.download_dir.as_ref().is_some_and(|dir| !dir.is_absolute()) {
+            return Err(Error::invalid("downloadDir should be an absolute path"));
+        }
+        for (name, endpoint) in [("innomi", &self.endpoints.innomi), ("pasteRs", &self.endpoints.paste_rs)] {
+            match reqwest::Url::parse(endpoint) {
+                Ok(url) if matches!(url.scheme(), "http" | "https") => {}
+                _ => return Err(Error::invalid(format!("The {} endpoint should be an http or https address, not {:?}", name, endpoint))),
+            }
+        }
+        Ok(())
+    }
+}
+
+// Keeps the settings in `dir` instead of the app config folder. Call it
+// before anything reads the settings.
+pub fn use_settings_dir(dir: PathBuf) {
+    if SETTINGS_DIR.set(dir).is_err() {
+        warn!("The settings folder was already set");
+    }
+}
+
+pub fn settings_dir() -> Result<PathBuf, Error> {
+    if let Some(dir) = SETTINGS_DIR.get() {
+        return Ok(dir.clone());
+    }
+    dirs::config_dir()
+        .map(|dir| dir.join(APP_IDENTIFIER))
+        .ok_or_else(|| Error::Io("No config folder found for the settings".to_string()))
+}
+
+fn cached() -> &'static Mutex<Option<Settings>> {
+    static SETTINGS: OnceLock<Mutex<Option<Settings>>> = OnceLock::new();
+    SETTINGS.get_or_init(|| Mutex::new(None))
+}
+
+// Reads the settings file, the defaults when there is none yet
+pub fn load() -> Result<Settings, Error> {
+    let path = settings_dir()?.join(SETTINGS_FILE);
+    if !path.exists() {
+        return Ok(Settings::default());
+    }
+    let json = fs::read_to_string(&path).map_err(|e| Error::io(format!("Failed to read {}", path.display()), e))?;
+    let mut settings: Settings = serde_json::from_str(&json).map_err(|e| Error::Decode(format!("Failed to parse {}: {}", path.display(), e)))?;
+    settings.validate().map_err(|e| e.context(path.display()))?;
+    // Nothing to migrate yet, files from older versions only lack fields
+    settings.version = SETTINGS_VERSION;
+    Ok(settings)
+}
+
+// The settings the pipeline runs with. A broken settings file is logged and
+// the defaults are used, so uploads and downloads keep working.
+pub fn current() -> Settings {
+    let mut cached = cached().lock().unwrap_or_else(PoisonError::into_inner);
+    cached.get_or_insert_with(|| load().unwrap_or_else(|e| {
+        warn!("Using the default settings: {}", e);
+        Settings::default()
+    })).clone()
+}
+
+// Checks and saves `settings`, which apply from the next upload or download on
+pub fn update(mut settings: Settings) -> Result<Settings, Error> {
+    settings.version = SETTINGS_VERSION;
+    settings.validate()?;
+    let dir = settings_dir()?;
+    fs::create_dir_all(&dir).map_err(|e| Error::io(format!("Failed to create {}", dir.display()), e))?;
+    let json = serde_json::to_string_pretty(&settings)?;
+    tree::write_atomically(&dir, &dir.join(SETTINGS_FILE), json.as_bytes())?;
+    info!("Saved the settings to {}", dir.join(SETTINGS_FILE).display());
+    *cached().lock().unwrap_or_else(PoisonError::into_inner) = Some(settings.clone());
+    Ok(settings)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn validate_only_takes_the_expire_values_innomi_offers() {
+        for expire in EXPIRE_VALUES {
+            let settings = Settings { part_expire: expire.to_string(), ..Settings::default() };
+            settings.validate().unwrap();
+        }
+        for expire in ["7m", "2h", "99999999999999999d", ""] {
+            let settings = Settings { manifest_expire: expire.to_string(), ..Settings::default() };
+            assert!(matches!(settings.validate(), Err(Error::Invalid(_))), "{}", expire);
+        }
+    }
+
+    #[test]
+    fn older_files_keep_expired_shares() {
+        let settings: Settings = serde_json::from_str(r#"{ "version": 1, "partExpire": "1d" }"#).unwrap();
+        assert!(!settings.drop_expired);
+        let settings: Settings = serde_json::from_str(r#"{ "dropExpired": true }"#).unwrap();
+        assert!(settings.drop_expired);
+    }
+}
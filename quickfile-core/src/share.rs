@@ -0,0 +1,924 @@
+use std::fs;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use serde::{Serialize, Deserialize};
+use reqwest::Client;
+use futures::future::join_all;
+use futures::stream::{self, StreamExt};
+use openssl::sha::Sha256;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::sync::Semaphore;
+use tokio::task::JoinHandle;
+use tracing::{Instrument, debug, debug_span, info, instrument, warn};
+use crate::backend::{Backend, Location, expire_duration, unix_now};
+use crate::compression::{self, CompressionOptions};
+use crate::encoding::Encoding;
+use crate::error::Error;
+use crate::liveness;
+use crate::logging;
+use crate::history::{self, HistoryEntry, HistoryFile, ReceivedEntry, ReceivedFile, ShareStatus};
+use crate::manifest::{self, Manifest, FileEntry, Mirrors, Redirect, StreamInfo, MANIFEST_VERSION, new_file_id};
+use crate::parity::{self, ParityOptions, Recovery};
+use crate::settings;
+use crate::tree::{self, ConflictPolicy};
+
+// Redirects followed before giving up on a link
+const MAX_REDIRECTS: usize = 4;
+// Path of the file in a share sent from a stream without a name
+const STREAM_FILE_NAME: &str = "stream";
+// Manifests of kept alive shares are uploaded with this expiry, so they're
+// still there when the parts are refreshed. Every refresh uploads a new one.
+pub const KEEP_ALIVE_MANIFEST_EXPIRE: &str = "14d";
+
+#[derive(Serialize, Deserialize, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct UploadOptions {
+    #[serde(default)]
+    pub compression: Option<CompressionOptions>,
+    // Chunk encoding, defaults to the densest one the backend preserves
+    #[serde(default)]
+    pub encoding: Option<Encoding>,
+    // Every part and the manifest are stored on each of these, innomi when empty
+    #[serde(default)]
+    pub backends: Vec<Backend>,
+    // Reed-Solomon parity parts, none when unset
+    #[serde(default)]
+    pub parity: Option<ParityOptions>,
+    // Upload the share again before it expires, see `liveness::refresh_due`
+    #[serde(default)]
+    pub keep_alive: bool,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RebuildReport {
+    pub id: String,
+    pub path: String,
+    // None when the file was skipped because it already existed, or was
+    // written to a stream
+    pub saved_to: Option<PathBuf>,
+    // Size and hex SHA-256 of the rebuilt file, None when skipped
+    pub size: Option<u64>,
+    pub sha256: Option<String>,
+    pub parts: usize,
+    // One per part, empty when skipped
+    pub part_stats: Vec<PartStats>,
+    #[serde(flatten)]
+    pub recovery: Recovery,
+}
+
+// How one part of a rebuilt file was downloaded, `part` counts from 1
+#[derive(Serialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct PartStats {
+    pub part: usize,
+    // Decoded size and the copy it came from, None when every copy failed
+    // and the part was restored from parity
+    pub bytes: Option<usize>,
+    pub mirror: Option<String>,
+}
+
+// What `write_file` wrote, `sha256` in hex
+pub struct Written {
+    pub size: u64,
+    pub sha256: String,
+    pub recovery: Recovery,
+    pub part_stats: Vec<PartStats>,
+}
+
+impl UploadOptions {
+    pub fn backends(&self) -> Vec<Backend> {
+        if self.backends.is_empty() {
+            vec![Backend::default()]
+        } else {
+            self.backends.clone()
+        }
+    }
+}
+
+// Uploads `text` to every backend, succeeding if at least one copy was stored
+pub async fn upload_to_mirrors(client: &Client, backends: &[Backend], text: String, expire: &str) -> Result<Mirrors, Error> {
+    if backends.is_empty() {
+        return Err(Error::invalid("No backends to upload to"));
+    }
+    let uploads = backends.iter().map(|backend| backend.post_paste(client, text.clone(), expire));
+    let mut locations = vec![];
+    let mut errors = vec![];
+    for (backend, result) in backends.iter().zip(join_all(uploads).await) {
+        match result {
+            Ok(id) => locations.push(Location { backend: backend.clone(), id }),
+            Err(e) => errors.push(e.context(format!("{:?}", backend))),
+        }
+    }
+    if locations.is_empty() {
+        // Every copy failed, the first failure decides the kind
+        let message = errors.iter().map(Error::to_string).collect::<Vec<String>>().join(", ");
+        return Err(errors.remove(0).map_message(|_| message));
+    }
+    if !errors.is_empty() {
+        let message = errors.iter().map(Error::to_string).collect::<Vec<String>>().join(", ");
+        warn!("Stored {} of {} copies, failed on {}", locations.len(), backends.len(), message);
+    }
+    Ok(Mirrors(locations))
+}
+
+// Uploads parts as they're produced, with at most the configured number of
+// transfers in flight so a fast reader can't pile up parts in memory
+struct PartUploader {
+    client: Arc<Client>,
+    backends: Arc<Vec<Backend>>,
+    encoding: Encoding,
+    expire: String,
+    slots: Arc<Semaphore>,
+    uploads: Vec<JoinHandle<Result<Mirrors, Error>>>,
+    stored_size: u64,
+}
+
+impl PartUploader {
+    async fn push(&mut self, chunk: &[u8]) -> Result<(), Error> {
+        let text = self.encoding.encode(chunk);
+        self.stored_size += text.len() as u64;
+        let slot = Arc::clone(&self.slots).acquire_owned().await.map_err(|_| Error::Cancelled)?;
+        let client = Arc::clone(&self.client);
+        let backends = Arc::clone(&self.backends);
+        let expire = self.expire.clone();
+        let part = debug_span!("part", index = self.uploads.len() + 1);
+        self.uploads.push(tokio::spawn(async move {
+            let result = upload_to_mirrors(&client, &backends, text, &expire).await;
+            drop(slot);
+            result
+        }.instrument(part)));
+        Ok(())
+    }
+
+    // Waits for every part, returns their mirrors in order and the number of
+    // characters uploaded
+    async fn finish(self, name: &str) -> Result<(Vec<Mirrors>, u64), Error> {
+        let total = self.uploads.len();
+        let mut parts = vec![];
+        let mut first_error = None;
+        for result in join_all(self.uploads).await {
+            match result.map_err(join_error).and_then(|result| result) {
+                Ok(mirrors) => parts.push(mirrors),
+                Err(e) => {
+                    warn!("Failed to upload a part of {}: {}", name, e);
+                    first_error.get_or_insert(e);
+                }
+            }
+        }
+        if let Some(e) = first_error {
+            let uploaded = parts.len();
+            return Err(e.map_message(|message| format!("Only {} of {} parts of {} were uploaded: {}", uploaded, total, name, message)));
+        }
+        Ok((parts, self.stored_size))
+    }
+}
+
+// A part task that didn't finish, because the runtime shut down or it panicked
+fn join_error(e: tokio::task::JoinError) -> Error {
+    match e.is_cancelled() {
+        true => Error::Cancelled,
+        false => Error::Internal(format!("A part task stopped unexpectedly: {}", e)),
+    }
+}
+
+// Fills `buffer` from `reader`, short only at the end of the stream
+async fn read_chunk<R: AsyncRead + Unpin>(reader: &mut R, buffer: &mut [u8]) -> Result<usize, Error> {
+    let mut filled = 0;
+    while filled < buffer.len() {
+        match reader.read(&mut buffer[filled..]).await.map_err(|e| Error::io("Failed to read input", e))? {
+            0 => break,
+            read => filled += read,
+        }
+    }
+    Ok(filled)
+}
+
+// Uploads one file, returns its manifest entry and the number of characters uploaded
+pub async fn upload_file(file_path: &Path, relative_path: String, options: &UploadOptions) -> Result<(FileEntry, u64), Error> {
+    let file = tokio::fs::File::open(file_path).await.map_err(|e| Error::io(format!("Failed to read {}", file_path.display()), e))?;
+    upload_reader(file, relative_path, options).await
+}
+
+// Uploads everything `reader` produces as one file. Parts are compressed,
+// encoded and uploaded while reading, so nothing goes through temporary
+// files. With parity one stripe of compressed content is held in memory.
+#[instrument(name = "file", skip_all, fields(path = %relative_path))]
+pub async fn upload_reader<R: AsyncRead + Unpin>(mut reader: R, relative_path: String, options: &UploadOptions) -> Result<(FileEntry, u64), Error> {
+    // Pick a chunk encoding every backend can store, the first backend's
+    // preferred one if possible
+    let backends = Arc::new(options.backends());
+    let encoding = options.encoding.unwrap_or_else(|| {
+        let preferred = backends[0].preferred_encoding();
+        if backends.iter().all(|backend| backend.preserves(preferred)) { preferred } else { Encoding::Base64 }
+    });
+    if let Some(backend) = backends.iter().find(|backend| !backend.preserves(encoding)) {
+        return Err(Error::invalid(format!("{:?} can't store {:?} encoded parts", backend, encoding)));
+    }
+
+    // Split the content into the largest chunks every backend accepts, or
+    // the configured size if that's smaller
+    let settings = settings::current();
+    let client = Arc::new(Client::new());
+    let mut max_paste_size = settings.max_chunk_size.unwrap_or(usize::MAX);
+    for backend in backends.iter() {
+        max_paste_size = max_paste_size.min(backend.max_paste_size(&client, encoding).await);
+    }
+    let chunk_size = encoding.raw_chunk_size(max_paste_size);
+    let mut uploader = PartUploader {
+        client,
+        backends,
+        encoding,
+        expire: settings.part_expire,
+        slots: Arc::new(Semaphore::new(settings.max_parallel_transfers)),
+        uploads: vec![],
+        stored_size: 0,
+    };
+
+    // Compress the content unless the start of it already looks compressed
+    let mut buffer = vec![0; chunk_size];
+    let mut read = read_chunk(&mut reader, &mut buffer).await?;
+    let codec = compression::choose_codec(&buffer[..read], options.compression);
+    let level = options.compression.and_then(|c| c.level);
+    let mut compressor = compression::Compressor::new(codec, level)?;
+    let mut size = 0;
+    let mut compressed_size = 0;
+    let mut parity_encoder = options.parity.map(|parity_options| parity::Encoder::new(chunk_size, parity_options)).transpose()?;
+    while read > 0 {
+        size += read as u64;
+        compressor.write(&buffer[..read])?;
+        for chunk in compressor.take_chunks(chunk_size) {
+            compressed_size += chunk.len() as u64;
+            match parity_encoder.as_mut() {
+                Some(encoder) => {
+                    for shard in encoder.write(&chunk)? {
+                        uploader.push(&shard).await?;
+                    }
+                }
+                None => uploader.push(&chunk).await?,
+            }
+        }
+        read = read_chunk(&mut reader, &mut buffer).await?;
+    }
+    let rest = compressor.finish()?;
+    compressed_size += rest.len() as u64;
+    debug!("Compressed {} with {:?}: {} -> {} bytes", relative_path, codec, size, compressed_size);
+
+    let parity = match parity_encoder {
+        Some(mut encoder) => {
+            let mut shards = encoder.write(&rest)?;
+            let (parity, last_shards) = encoder.finish()?;
+            shards.extend(last_shards);
+            info!("Added {} parity parts to {}", parity.parity_part_count(), relative_path);
+            for shard in shards {
+                uploader.push(&shard).await?;
+            }
+            Some(parity)
+        }
+        None => {
+            for chunk in rest.chunks(chunk_size) {
+                uploader.push(chunk).await?;
+            }
+            None
+        }
+    };
+
+    let (parts, stored_size) = uploader.finish(&relative_path).await?;
+    let entry = FileEntry {
+        id: new_file_id()?,
+        path: relative_path,
+        size: Some(size),
+        compression: codec,
+        encoding,
+        parity,
+        parts,
+    };
+
+    Ok((entry, stored_size))
+}
+
+// Expire value for the manifest of a share
+fn manifest_expire(keep_alive: bool) -> String {
+    match keep_alive {
+        true => KEEP_ALIVE_MANIFEST_EXPIRE.to_string(),
+        false => settings::current().manifest_expire,
+    }
+}
+
+pub async fn upload_manifest(backends: &[Backend], manifest_json: String, expire: &str) -> Result<Vec<Location>, Error> {
+    let client = Client::new();
+    upload_to_mirrors(&client, backends, manifest_json, expire).await
+        .map(|mirrors| mirrors.0)
+        .map_err(|e| e.context("Failed to upload file_data.json"))
+}
+
+// When a share uploaded at `created_at` stops working. The link points at the
+// manifest on the first backend, a part lives as long as its longest-lived mirror.
+fn share_expiry(backends: &[Backend], created_at: u64, manifest_expire: &str) -> Result<Option<u64>, Error> {
+    let first = backends.first().ok_or_else(|| Error::invalid("No backends to upload to"))?;
+    let mut lifetimes = vec![];
+    if first.expires() {
+        lifetimes.push(expire_duration(manifest_expire)?);
+    }
+    if backends.iter().all(Backend::expires) {
+        lifetimes.push(expire_duration(&settings::current().part_expire)?);
+    }
+    Ok(lifetimes.into_iter().flatten().min().map(|lifetime| created_at + lifetime.as_secs()))
+}
+
+async fn download_json(client: Arc<Client>, location: &Location) -> Result<String, Error> {
+    let text = location.fetch(&client).await.map_err(|e| {
+        warn!("Failed to download {}: {}", location, e);
+        e
+    })?;
+    debug!("Downloaded {} characters from {}", text.len(), location);
+    Ok(text)
+}
+
+// Paste hosts delete expired pastes
+fn gone_as_expired(e: Error, location: &Location) -> Error {
+    match e {
+        Error::Http { status: Some(404 | 410), .. } => Error::Expired(format!("{} is gone from the host, the share has probably expired", location)),
+        e => e,
+    }
+}
+
+// A part as it was downloaded and decoded
+pub struct FetchedPart {
+    pub data: Vec<u8>,
+    pub location: Location,
+}
+
+// Tries every mirror of a part in order until one of them can be decoded,
+// the error of the last one otherwise. Takes what it needs by value, a
+// future borrowing from the caller's closure wouldn't be Send.
+async fn fetch_part(client: Arc<Client>, mirrors: Mirrors, encoding: Encoding) -> Result<FetchedPart, Error> {
+    let mut last_error = Error::Decode("The part has no mirrors".to_string());
+    for location in mirrors.0 {
+        match location.fetch(&client).await {
+            Ok(content) => match encoding.decode(&content) {
+                Ok(data) => {
+                    debug!("Downloaded {} bytes from {}", data.len(), location);
+                    return Ok(FetchedPart { data, location });
+                }
+                Err(e) => {
+                    warn!("Failed to decode the part at {}: {}", location, e);
+                    last_error = e.context(&location);
+                }
+            },
+            Err(e) => {
+                warn!("Failed to download the part at {}: {}", location, e);
+                last_error = gone_as_expired(e, &location);
+            }
+        }
+    }
+    Err(last_error)
+}
+
+// The error for a part every mirror failed on. Text that was fetched but
+// doesn't decode is an integrity problem, parts that are gone or couldn't be
+// reached keep their kind.
+fn part_error(entry: &FileEntry, index: usize, e: Error) -> Error {
+    let context = format!("Part {} of {} of {}", index + 1, entry.parts.len(), entry.path);
+    match e {
+        Error::Decode(message) => Error::integrity(&entry.path, Some(index + 1), format!("{}: {}", context, message)),
+        e => e.context(context),
+    }
+}
+
+// Fetches the manifest from the first of `links` that works
+pub async fn download_manifest_from(client: Arc<Client>, links: &[String]) -> Result<Manifest, Error> {
+    let mut errors = vec![];
+    for link in links {
+        match download_manifest(Arc::clone(&client), link).await {
+            Ok(manifest) => return Ok(manifest),
+            Err(e) => errors.push(e.context(link)),
+        }
+    }
+    let message = errors.iter().map(Error::to_string).collect::<Vec<String>>().join(", ");
+    match errors.into_iter().next() {
+        Some(e) => Err(e.map_message(|_| format!("Failed to download the manifest from {}", message))),
+        None => Err(Error::invalid("There is no link to download the manifest from")),
+    }
+}
+
+// Which local files a share opened through `link` may be read from. Local
+// locations are paths on this machine, so they're only followed for the
+// user's own shares, or in the folder of a local link they opened, never
+// just because a downloaded manifest lists them.
+struct LocalAccess {
+    own_share: bool,
+    folder: Option<PathBuf>,
+}
+
+impl LocalAccess {
+    fn for_link(link: &str) -> Self {
+        let own_share = history::load::<HistoryEntry>()
+            .map(|entries| entries.iter().any(|entry| entry.links().iter().any(|own| own == link)))
+            .unwrap_or(false);
+        let folder = match Location::parse(link) {
+            Ok(Location { backend: Backend::Local(dir), .. }) => Some(dir),
+            _ => None,
+        };
+        LocalAccess { own_share, folder }
+    }
+
+    fn allows(&self, location: &Location) -> bool {
+        match &location.backend {
+            Backend::Local(dir) => self.own_share || self.folder.as_ref() == Some(dir),
+            _ => true,
+        }
+    }
+
+    // Drops the part locations the share can't read, the other mirrors are used instead
+    fn restrict(&self, manifest: &mut Manifest) {
+        for mirrors in manifest.files.iter_mut().flat_map(|entry| entry.parts.iter_mut()) {
+            mirrors.0.retain(|location| {
+                let allowed = self.allows(location);
+                if !allowed {
+                    warn!("Ignoring part location {}, the share can't read local files outside its folder", location);
+                }
+                allowed
+            });
+        }
+    }
+}
+
+// Fetches the manifest behind `title`, following redirects
+pub async fn download_manifest(client: Arc<Client>, title: &str) -> Result<Manifest, Error> {
+    let access = LocalAccess::for_link(title);
+    let mut link = title.to_string();
+    for _ in 0..=MAX_REDIRECTS {
+        let initial_location = Location::parse(&link)?;
+        if !access.allows(&initial_location) {
+            return Err(Error::refused(format!("Refusing to follow {} to {}, a local file outside the share's folder", title, initial_location)));
+        }
+        let initial_json = download_json(Arc::clone(&client), &initial_location).await.map_err(|e| gone_as_expired(e, &initial_location))?;
+
+        if let Some(target) = manifest::redirect_target(&initial_json) {
+            info!("{} redirects to {}", initial_location, target);
+            link = target;
+            continue;
+        }
+        let mut manifest = Manifest::parse(&initial_json).map_err(|e| {
+            warn!("Failed to parse the manifest at {}: {}", initial_location, e);
+            e
+        })?;
+        access.restrict(&mut manifest);
+        return Ok(manifest);
+    }
+    Err(Error::refused(format!("{} redirects more than {} times", title, MAX_REDIRECTS)))
+}
+
+// Downloads and decodes every part of a file, with the error of the last
+// mirror for parts none of them could provide
+pub async fn download_parts(client: Arc<Client>, entry: &FileEntry, parts: Range<usize>) -> Vec<Result<FetchedPart, Error>> {
+    stream::iter(parts.map(|index| (index, entry.parts[index].clone())))
+        .map(|(index, mirrors)| fetch_part(Arc::clone(&client), mirrors, entry.encoding).instrument(debug_span!("part", index = index + 1)))
+        .buffered(settings::current().max_parallel_transfers)
+        .collect()
+        .await
+}
+
+// Splits parts downloaded from `first` on into the shards parity works on and
+// their stats, keeping the error of the first lost part
+fn sort_parts(entry: &FileEntry, first: usize, parts: Vec<Result<FetchedPart, Error>>) -> (Vec<Option<Vec<u8>>>, Vec<PartStats>, Option<Error>) {
+    let mut shards = vec![];
+    let mut part_stats = vec![];
+    let mut first_error = None;
+    for (index, part) in (first..).zip(parts) {
+        match part {
+            Ok(FetchedPart { data, location }) => {
+                part_stats.push(PartStats { part: index + 1, bytes: Some(data.len()), mirror: Some(location.to_string()) });
+                shards.push(Some(data));
+            }
+            Err(e) => {
+                part_stats.push(PartStats { part: index + 1, bytes: None, mirror: None });
+                shards.push(None);
+                first_error.get_or_insert_with(|| part_error(entry, index, e));
+            }
+        }
+    }
+    (shards, part_stats, first_error)
+}
+
+// Rebuilds one file into `writer`. Without parity the parts are downloaded
+// a few ahead and written in order, with parity one stripe at a time, so
+// the file is never held in memory as a whole.
+#[instrument(name = "file", skip_all, fields(path = %entry.path))]
+pub async fn write_file<W: AsyncWrite + Unpin>(client: Arc<Client>, entry: &FileEntry, writer: &mut W) -> Result<Written, Error> {
+    let mut hasher = Sha256::new();
+    let mut size = 0;
+    let mut write = |data: Vec<u8>| {
+        hasher.update(&data);
+        size += data.len() as u64;
+        data
+    };
+    let written = |e: std::io::Error| Error::io(format!("Failed to write {}", entry.path), e);
+
+    let mut decompressor = compression::Decompressor::new(entry.compression)?;
+    let (recovery, part_stats) = match &entry.parity {
+        Some(parity) => {
+            let mut recovery = Recovery { parity_parts: parity.parity_part_count(), ..Recovery::default() };
+            let mut part_stats = vec![];
+            let mut remaining = parity.length;
+            for (stripe_index, parts) in parity.stripe_parts().into_iter().enumerate() {
+                let first = parts.start;
+                let (mut shards, stats, part_error) = sort_parts(entry, first, download_parts(Arc::clone(&client), entry, parts).await);
+                part_stats.extend(stats);
+                // Too many lost parts fail with why the first one was lost
+                let (mut data, lost) = parity::reconstruct_stripe(parity, stripe_index, &mut shards).map_err(|e| match part_error {
+                    Some(part_error) => part_error,
+                    None => Error::integrity(&entry.path, None, format!("Failed to rebuild {}: {}", entry.path, e)),
+                })?;
+                recovery.add_stripe(lost);
+                data.truncate(remaining);
+                remaining -= data.len();
+                decompressor.write(&data)
+                    .map_err(|e| Error::integrity(&entry.path, None, format!("Failed to rebuild {}: {}", entry.path, e)))?;
+                let data = write(decompressor.take_output());
+                writer.write_all(&data).await.map_err(written)?;
+            }
+            (recovery, part_stats)
+        }
+        None => {
+            let encoding = entry.encoding;
+            let mut parts = stream::iter(entry.parts.clone().into_iter().enumerate())
+                .map(|(index, mirrors)| fetch_part(Arc::clone(&client), mirrors, encoding).instrument(debug_span!("part", index = index + 1)))
+                .buffered(settings::current().max_parallel_transfers)
+                .enumerate();
+            let mut part_stats = vec![];
+            while let Some((index, part)) = parts.next().await {
+                let FetchedPart { data: part, location } = part.map_err(|e| part_error(entry, index, e))?;
+                part_stats.push(PartStats { part: index + 1, bytes: Some(part.len()), mirror: Some(location.to_string()) });
+                decompressor.write(&part)
+                    .map_err(|e| Error::integrity(&entry.path, Some(index + 1), format!("Failed to rebuild {}: {}", entry.path, e)))?;
+                let data = write(decompressor.take_output());
+                writer.write_all(&data).await.map_err(written)?;
+            }
+            (Recovery::default(), part_stats)
+        }
+    };
+    let data = decompressor.finish()
+        .map_err(|e| Error::integrity(&entry.path, None, format!("Failed to rebuild {}: {}", entry.path, e)))?;
+    let data = write(data);
+    writer.write_all(&data).await.map_err(written)?;
+    writer.flush().await.map_err(written)?;
+
+    if entry.size.is_some_and(|expected| expected != size) {
+        return Err(Error::integrity(&entry.path, None, format!("Rebuilt {} is {} bytes, the manifest says {}", entry.path, size, entry.size.unwrap_or_default())));
+    }
+    Ok(Written { size, sha256: hex::encode(hasher.finish()), recovery, part_stats })
+}
+
+// Downloads every part of a share and stores it again on `backends`, parts
+// lost on every mirror are restored from parity where possible. Returns the
+// manifest of the copy, it's up to the caller to upload it with
+// `manifest_expire`. Nothing is decompressed or written to disk.
+pub async fn copy_share(client: Arc<Client>, manifest: Manifest, backends: &[Backend], manifest_expire: &str) -> Result<Manifest, Error> {
+    for entry in &manifest.files {
+        if let Some(backend) = backends.iter().find(|backend| !backend.preserves(entry.encoding)) {
+            return Err(Error::invalid(format!("{:?} can't store the {:?} encoded parts of {}", backend, entry.encoding, entry.path)));
+        }
+    }
+    let created_at = unix_now();
+    let mut copy = Manifest {
+        version: MANIFEST_VERSION,
+        files: vec![],
+        directories: manifest.directories,
+        created_at: Some(created_at),
+        expires_at: share_expiry(backends, created_at, manifest_expire)?,
+        stream: manifest.stream,
+    };
+    for entry in manifest.files {
+        copy.files.push(copy_file(&client, entry, backends).await?);
+    }
+    Ok(copy)
+}
+
+// Downloads the parts of one file and stores them again on `backends`
+#[instrument(name = "file", skip_all, fields(path = %entry.path))]
+async fn copy_file(client: &Arc<Client>, entry: FileEntry, backends: &[Backend]) -> Result<FileEntry, Error> {
+    let (shards, _, part_error) = sort_parts(&entry, 0, download_parts(Arc::clone(client), &entry, 0..entry.parts.len()).await);
+    let parts = match (&entry.parity, part_error) {
+        (_, None) => shards.into_iter().flatten().collect(),
+        // Encoding the reconstructed data again gives back the same shards
+        (Some(parity), Some(part_error)) => {
+            let (data, _) = parity::reconstruct(parity, shards).map_err(|_| part_error)?;
+            let options = ParityOptions { data_shards: parity.data_shards, parity_shards: parity.parity_shards };
+            parity::encode(&data, parity.shard_size, options)?.1
+        }
+        (None, Some(part_error)) => return Err(part_error),
+    };
+
+    let expire = settings::current().part_expire;
+    let uploads = parts.iter().enumerate().map(|(index, part)| {
+        upload_to_mirrors(client, backends, entry.encoding.encode(part), &expire).instrument(debug_span!("part", index = index + 1))
+    });
+    let mirrors = join_all(uploads).await.into_iter()
+        .collect::<Result<Vec<Mirrors>, Error>>()
+        .map_err(|e| e.context(format!("Failed to upload a part of {}", entry.path)))?;
+    info!("Copied {} parts of {}", mirrors.len(), entry.path);
+    Ok(FileEntry { parts: mirrors, ..entry })
+}
+
+// Rebuilds the files with the given ids, or all of them when `file_ids` is
+// None. `links` are copies of the same manifest, tried in order.
+pub async fn rebuild(links: Vec<String>, file_ids: Option<Vec<String>>, destination: PathBuf, conflict: ConflictPolicy, ask: &(dyn Fn(&Path) -> bool + Sync)) -> Result<Vec<RebuildReport>, Error> {
+    let client = Arc::new(Client::new());
+    let mut manifest = download_manifest_from(Arc::clone(&client), &links).await?;
+
+    if let Some(file_ids) = file_ids {
+        if let Some(unknown) = file_ids.iter().find(|id| !manifest.files.iter().any(|entry| &entry.id == *id)) {
+            return Err(Error::invalid(format!("The share has no file with id {}", unknown)));
+        }
+        manifest.files.retain(|entry| file_ids.contains(&entry.id));
+        // Empty directories only come along when rebuilding everything
+        manifest.directories.clear();
+    }
+
+    // Check every path in the manifest before downloading or writing anything
+    let file_paths = manifest.files.iter()
+        .map(|entry| tree::sanitize_path(&entry.path))
+        .collect::<Result<Vec<PathBuf>, Error>>()?;
+    let directory_paths = manifest.directories.iter()
+        .map(|directory| tree::sanitize_path(directory))
+        .collect::<Result<Vec<PathBuf>, Error>>()?;
+    fs::create_dir_all(&destination).map_err(|e| Error::io(format!("Failed to create {}", destination.display()), e))?;
+
+    let skipped = |entry: &FileEntry| {
+        info!("Skipping {}, it already exists", entry.path);
+        RebuildReport {
+            id: entry.id.clone(),
+            path: entry.path.clone(),
+            saved_to: None,
+            size: None,
+            sha256: None,
+            parts: entry.parts.len(),
+            part_stats: vec![],
+            recovery: Recovery::default(),
+        }
+    };
+
+    let mut reports = vec![];
+    for (entry, file_path) in manifest.files.into_iter().zip(file_paths) {
+        let filename = &entry.path;
+        let Some(target) = tree::resolve_conflict(&destination.join(file_path), conflict, ask) else {
+            reports.push(skipped(&entry));
+            continue;
+        };
+        let temp_file = tree::create_atomically(&destination, &target.path)?;
+        let mut writer = tokio::fs::File::from_std(temp_file.reopen().map_err(|e| Error::io(format!("Failed to write {}", target.path.display()), e))?);
+        let Written { size, sha256, recovery, part_stats } = write_file(Arc::clone(&client), &entry, &mut writer).await?;
+        // Something else may have written there while downloading
+        let Some(download_path) = tree::persist_resolved(temp_file, &target, conflict)? else {
+            reports.push(skipped(&entry));
+            continue;
+        };
+
+        info!("Saved {} to {}", filename, download_path.display());
+        if recovery.lost_parts > 0 {
+            warn!("Recovered {} lost parts of {} using parity, worst stripe lost {}", recovery.lost_parts, filename, recovery.worst_stripe_lost);
+        }
+        reports.push(RebuildReport {
+            id: entry.id.clone(),
+            path: entry.path.clone(),
+            saved_to: Some(download_path),
+            size: Some(size),
+            sha256: Some(sha256),
+            parts: entry.parts.len(),
+            part_stats,
+            recovery,
+        });
+    }
+
+    for directory in directory_paths {
+        let directory_path = destination.join(&directory);
+        tree::create_dir_inside(&destination, &directory_path)?;
+        debug!("Created empty directory {}", directory_path.display());
+    }
+
+    Ok(reports)
+}
+
+pub fn redirect_json(link: &str) -> Result<String, Error> {
+    Ok(serde_json::to_string(&Redirect { redirect: link.to_string() })?)
+}
+
+// Copies a share to `backends` and uploads the manifest of the copy. Returns
+// that manifest and the links to it, the first one being the new share link.
+pub async fn republish(client: Arc<Client>, manifest: Manifest, backends: &[Backend], keep_alive: bool) -> Result<(Manifest, Vec<String>), Error> {
+    let manifest_expire = manifest_expire(keep_alive);
+    let copy = copy_share(client, manifest, backends, &manifest_expire).await?;
+    let manifest_json = serde_json::to_string_pretty(&copy)?;
+    let locations = upload_manifest(backends, manifest_json, &manifest_expire).await?;
+    Ok((copy, locations.iter().map(|location| location.to_string()).collect()))
+}
+
+// Makes the pointer of a history entry redirect to `link`, if it has one
+pub fn update_pointer(entry: &HistoryEntry, link: &str) -> Result<(), Error> {
+    if let Some(pointer) = &entry.pointer {
+        let pointer = Location::parse(pointer)?;
+        pointer.backend.rewrite_paste(&pointer.id, &redirect_json(link)?)?;
+    }
+    Ok(())
+}
+
+// Records a republished copy in a history entry
+pub fn point_entry_at(entry: &mut HistoryEntry, copy: &Manifest, links: &[String]) {
+    entry.link = links[0].clone();
+    entry.mirrors = links[1..].to_vec();
+    entry.expires_at = copy.expires_at;
+    entry.refreshed_at = copy.created_at;
+    entry.alive = Some(true);
+    entry.checked_at = copy.created_at;
+}
+
+// A finished upload
+pub struct Sent {
+    // The share link, the first copy of the manifest
+    pub link: String,
+    // Links to the other copies of the manifest
+    pub mirrors: Vec<String>,
+    pub manifest: Manifest,
+}
+
+// Uploads files and directories as one share and records it in the history
+#[instrument(name = "send", skip_all, fields(job = logging::next_job(), paths = paths.len()))]
+pub async fn send(paths: &[String], options: &UploadOptions) -> Result<Sent, Error> {
+    let mut manifest = Manifest::default();
+    let created_at = unix_now();
+    let inputs = tree::collect_inputs(paths)?;
+    let mut stored_size = 0;
+
+    for (file_path, name) in inputs.files {
+        // Process each file separately
+        let (entry, file_stored_size) = upload_file(&file_path, name, options).await?;
+        manifest.files.push(entry);
+        stored_size += file_stored_size;
+    }
+    manifest.directories = inputs.empty_directories;
+    publish(manifest, created_at, stored_size, options).await
+}
+
+// Uploads a stream, e.g. stdin, as a share with a single file named after it
+#[instrument(name = "send", skip_all, fields(job = logging::next_job(), stream = true))]
+pub async fn send_stream<R: AsyncRead + Unpin>(reader: R, name: Option<String>, options: &UploadOptions) -> Result<Sent, Error> {
+    let created_at = unix_now();
+    let path = name.clone().unwrap_or_else(|| STREAM_FILE_NAME.to_string());
+    let (entry, stored_size) = upload_reader(reader, path, options).await?;
+    let manifest = Manifest {
+        stream: Some(StreamInfo { name, length: entry.size }),
+        files: vec![entry],
+        ..Manifest::default()
+    };
+    publish(manifest, created_at, stored_size, options).await
+}
+
+// Uploads the manifest of a share whose parts are stored and records it
+async fn publish(mut manifest: Manifest, created_at: u64, stored_size: u64, options: &UploadOptions) -> Result<Sent, Error> {
+    let manifest_expire = manifest_expire(options.keep_alive);
+    manifest.created_at = Some(created_at);
+    manifest.expires_at = share_expiry(&options.backends(), created_at, &manifest_expire)?;
+
+    // Upload the manifest and get its title
+    let manifest_json = serde_json::to_string_pretty(&manifest)?;
+    let manifest_locations = upload_manifest(&options.backends(), manifest_json, &manifest_expire).await?;
+    let link = manifest_locations[0].to_string();
+    let mirrors: Vec<String> = manifest_locations[1..].iter().map(|location| location.to_string()).collect();
+    info!("Published the manifest on {} backends", manifest_locations.len());
+    let pointer = match options.keep_alive {
+        true => liveness::create_pointer(&options.backends(), &link).await?,
+        false => None,
+    };
+
+    // The share is up either way, so a history that can't be written only
+    // costs the entry, not the link
+    let recorded = new_file_id().and_then(|id| history::record(HistoryEntry {
+        id,
+        link: link.clone(),
+        mirrors: mirrors.clone(),
+        files: manifest.files.iter().map(|entry| HistoryFile { path: entry.path.clone(), size: entry.size }).collect(),
+        created_at: Some(created_at),
+        backends: options.backends(),
+        expire: Some(settings::current().part_expire),
+        expires_at: manifest.expires_at,
+        size: Some(manifest.files.iter().filter_map(|entry| entry.size).sum()),
+        stored_size: Some(stored_size),
+        alive: None,
+        checked_at: None,
+        status: ShareStatus::Live,
+        keep_alive: options.keep_alive,
+        pointer,
+        refreshed_at: None,
+    }));
+    if let Err(e) = recorded {
+        warn!("Failed to add the share to the history: {}", e);
+    }
+
+    Ok(Sent { link, mirrors, manifest })
+}
+
+// Rebuilds a share into `destination` and records what was saved in the
+// received history
+#[instrument(name = "receive", skip_all, fields(job = logging::next_job(), link = links.first().map(String::as_str)))]
+pub async fn receive(links: Vec<String>, file_ids: Option<Vec<String>>, destination: PathBuf, conflict: ConflictPolicy, ask: &(dyn Fn(&Path) -> bool + Sync)) -> Result<Vec<RebuildReport>, Error> {
+    let link = links.first().cloned().ok_or_else(|| Error::invalid("There is no link to receive from"))?;
+    let reports = rebuild(links, file_ids, destination.clone(), conflict, ask).await?;
+
+    let files: Vec<ReceivedFile> = reports.iter().filter_map(|report| Some(ReceivedFile {
+        path: report.path.clone(),
+        saved_to: report.saved_to.clone()?,
+        size: report.size?,
+        sha256: report.sha256.clone()?,
+    })).collect();
+    if !files.is_empty() {
+        // The files are saved either way
+        let recorded = new_file_id().and_then(|id| history::record(ReceivedEntry { id, link: link.clone(), received_at: unix_now(), destination, files }));
+        if let Err(e) = recorded {
+            warn!("Failed to add the share to the received history: {}", e);
+        }
+    }
+    Ok(reports)
+}
+
+// Rebuilds one file of a share into `writer`, e.g. stdout. `file_id` can be
+// left out when the share has a single file, as shares sent from a stream do.
+#[instrument(name = "receive", skip_all, fields(job = logging::next_job(), link = link, stream = true))]
+pub async fn receive_stream<W: AsyncWrite + Unpin>(link: &str, file_id: Option<&str>, writer: &mut W) -> Result<RebuildReport, Error> {
+    let client = Arc::new(Client::new());
+    let manifest = download_manifest(Arc::clone(&client), link).await?;
+    let entry = match file_id {
+        Some(file_id) => manifest.files.iter().find(|entry| entry.id == file_id)
+            .ok_or_else(|| Error::invalid(format!("The share has no file with id {}", file_id)))?,
+        None if manifest.files.len() == 1 => &manifest.files[0],
+        None => return Err(Error::invalid(format!("The share has {} files, pick the one to write out by its id", manifest.files.len()))),
+    };
+    let Written { size, sha256, recovery, part_stats } = write_file(client, entry, writer).await?;
+    Ok(RebuildReport {
+        id: entry.id.clone(),
+        path: entry.path.clone(),
+        saved_to: None,
+        size: Some(size),
+        sha256: Some(sha256),
+        parts: entry.parts.len(),
+        part_stats,
+        recovery,
+    })
+}
+
+// Moves a share to other backends: every part is downloaded, checked to
+// decode and uploaded again, then a new manifest is published. History
+// entries of the share, and their pointers, are switched to the new link.
+#[instrument(name = "migrate", skip_all, fields(job = logging::next_job(), link = title))]
+pub async fn migrate(title: &str, backends: &[Backend]) -> Result<String, Error> {
+    if backends.is_empty() {
+        return Err(Error::invalid("Choose at least one backend to migrate the share to"));
+    }
+    let entries: Vec<HistoryEntry> = history::load()?;
+    let migrated: Vec<&HistoryEntry> = entries.iter().filter(|entry| entry.links().iter().any(|link| link == title)).collect();
+    let keep_alive = migrated.iter().any(|entry| entry.keep_alive);
+
+    let client = Arc::new(Client::new());
+    let manifest = download_manifest(Arc::clone(&client), title).await?;
+    let (copy, links) = republish(client, manifest, backends, keep_alive).await?;
+    for entry in &migrated {
+        update_pointer(entry, &links[0])?;
+    }
+    history::update(|entries: &mut Vec<HistoryEntry>| {
+        for stored in entries.iter_mut().filter(|stored| migrated.iter().any(|entry| entry.id == stored.id)) {
+            point_entry_at(stored, &copy, &links);
+            stored.backends = backends.to_vec();
+        }
+    })?;
+    info!("Migrated the share to {:?}", backends);
+    Ok(links[0].clone())
+}
+
+// The app awaits these on Tauri's thread pool, so their futures have to be Send
+const _: fn() = || {
+    fn assert_send<T: Send>(_: T) {}
+    let ask = |_: &Path| false;
+    assert_send(send(&[], &UploadOptions::default()));
+    assert_send(send_stream(tokio::io::empty(), None, &UploadOptions::default()));
+    assert_send(receive(vec![], None, PathBuf::new(), ConflictPolicy::default(), &ask));
+    assert_send(receive_stream("", None, &mut tokio::io::sink()));
+    assert_send(migrate("", &[]));
+    assert_send(crate::inspect::inspect("", false));
+    assert_send(crate::liveness::check_history());
+    assert_send(crate::liveness::refresh_due());
+    assert_send(crate::liveness::set_keep_alive("", false));
+};
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn no_backends_is_an_error_rather_than_a_panic() {
+        assert!(matches!(share_expiry(&[], 0, "1d"), Err(Error::Invalid(_))));
+        let uploaded = upload_to_mirrors(&Client::new(), &[], "text".to_string(), "1d").await;
+        assert!(matches!(uploaded, Err(Error::Invalid(_))));
+    }
+}
@@ -0,0 +1,408 @@
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use serde::{Serialize, Deserialize};
+use tempfile::NamedTempFile;
+use walkdir::WalkDir;
+use tracing::info;
+use crate::error::Error;
+
+// Longest file or directory name most file systems accept, in bytes
+const MAX_NAME_LENGTH: usize = 255;
+// Names Windows refuses regardless of extension
+const RESERVED_NAMES: [&str; 22] = [
+    "CON", "PRN", "AUX", "NUL",
+    "COM1", "COM2", "COM3", "COM4", "COM5", "COM6", "COM7", "COM8", "COM9",
+    "LPT1", "LPT2", "LPT3", "LPT4", "LPT5", "LPT6", "LPT7", "LPT8", "LPT9",
+];
+
+// What to do when a rebuilt file would land on an existing one
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum ConflictPolicy {
+    // Keep both, the new file gets a numbered name
+    #[default]
+    Rename,
+    Skip,
+    Overwrite,
+    Ask,
+}
+
+// Files and empty directories picked for upload, with the path they get in
+// the manifest. Paths in the manifest are relative and always use `/`.
+#[derive(Default)]
+pub struct Inputs {
+    pub files: Vec<(PathBuf, String)>,
+    pub empty_directories: Vec<String>,
+}
+
+// Expands the picked paths. A picked file is stored under its name, a picked
+// directory under its name with everything below it. Symlinks are skipped,
+// following them could leave the picked tree or loop forever. So are FIFOs,
+// sockets and devices below a picked directory, reading them could block or
+// never end, and picking one is refused.
+pub fn collect_inputs(paths: &[String]) -> Result<Inputs, Error> {
+    let mut inputs = Inputs::default();
+    for path in paths {
+        let path = PathBuf::from(path);
+        let metadata = path.symlink_metadata().map_err(|e| Error::io(format!("Failed to read {}", path.display()), e))?;
+        if metadata.file_type().is_symlink() {
+            info!("Skipping symlink {}", path.display());
+            continue;
+        }
+        if !metadata.is_dir() && !metadata.is_file() {
+            return Err(Error::invalid(format!("Refusing to upload {}, it's not a regular file or directory", path.display())));
+        }
+        if metadata.is_file() {
+            let name = file_name(&path)?;
+            inputs.files.push((path, name));
+            continue;
+        }
+
+        let root = path.parent().map(Path::to_path_buf).unwrap_or_default();
+        for entry in WalkDir::new(&path).follow_links(false).sort_by_file_name() {
+            let entry = entry.map_err(|e| Error::io(format!("Failed to walk {}", path.display()), e))?;
+            let relative = relative_path(&root, entry.path())?;
+            if entry.file_type().is_symlink() {
+                info!("Skipping symlink {}", entry.path().display());
+            } else if entry.file_type().is_dir() {
+                let is_empty = entry.path().read_dir().map_err(|e| Error::io(format!("Failed to read {}", entry.path().display()), e))?.next().is_none();
+                if is_empty {
+                    inputs.empty_directories.push(relative);
+                }
+            } else if entry.file_type().is_file() {
+                inputs.files.push((entry.path().to_path_buf(), relative));
+            } else {
+                info!("Skipping {}, it's not a regular file", entry.path().display());
+            }
+        }
+    }
+    Ok(inputs)
+}
+
+// `path` if nothing is there yet, otherwise the first free `name (n).ext`
+fn unique_path(path: &Path) -> PathBuf {
+    if !path.exists() {
+        return path.to_path_buf();
+    }
+    let stem = path.file_stem().map(|stem| stem.to_string_lossy().to_string()).unwrap_or_default();
+    let extension = path.extension().map(|extension| format!(".{}", extension.to_string_lossy())).unwrap_or_default();
+    (1..)
+        .map(|n| path.with_file_name(format!("{} ({}){}", stem, n, extension)))
+        .find(|candidate| !candidate.exists())
+        .unwrap_or_else(|| path.to_path_buf())
+}
+
+// Where a rebuilt file goes, see `resolve_conflict`
+#[derive(Debug, PartialEq)]
+pub struct Target {
+    pub path: PathBuf,
+    // Whether a file that is at `path` by the time it's saved may be replaced
+    pub replace: bool,
+}
+
+// Where a file meant for `path` should be written, `None` to skip it. `ask`
+// decides for ConflictPolicy::Ask, true to overwrite and false to keep both.
+pub fn resolve_conflict(path: &Path, policy: ConflictPolicy, ask: impl FnOnce(&Path) -> bool) -> Option<Target> {
+    let replace = |path: &Path| Some(Target { path: path.to_path_buf(), replace: true });
+    let keep = |path: PathBuf| Some(Target { path, replace: false });
+    if !path.exists() {
+        return if policy == ConflictPolicy::Overwrite { replace(path) } else { keep(path.to_path_buf()) };
+    }
+    match policy {
+        ConflictPolicy::Rename => keep(unique_path(path)),
+        ConflictPolicy::Skip => None,
+        ConflictPolicy::Overwrite => replace(path),
+        ConflictPolicy::Ask if ask(path) => replace(path),
+        ConflictPolicy::Ask => keep(unique_path(path)),
+    }
+}
+
+// Turns a path from a manifest into a relative path that can't leave the
+// destination. Absolute paths and `..` are refused since only a crafted
+// share contains them, names that are merely invalid on some systems are
+// rewritten.
+pub fn sanitize_path(manifest_path: &str) -> Result<PathBuf, Error> {
+    let refuse = |reason: &str| Err(Error::refused(format!("Refusing to rebuild {:?}: {}", manifest_path, reason)));
+    if manifest_path.starts_with('/') || manifest_path.starts_with('\\') {
+        return refuse("absolute paths are not allowed");
+    }
+    let mut sanitized = PathBuf::new();
+    for (index, component) in manifest_path.split(['/', '\\']).enumerate() {
+        match component {
+            "" | "." => continue,
+            ".." => return refuse("paths can't point outside the destination"),
+            // Drive letters and other prefixes like C: or \\?\
+            _ if index == 0 && component.contains(':') => return refuse("absolute paths are not allowed"),
+            _ => sanitized.push(sanitize_name(component)),
+        }
+    }
+    if sanitized.as_os_str().is_empty() {
+        return refuse("the path is empty");
+    }
+    Ok(sanitized)
+}
+
+fn sanitize_name(name: &str) -> String {
+    // Control characters and characters Windows doesn't allow in names
+    let mut name: String = name.chars()
+        .map(|c| if c.is_control() || "<>:\"|?*".contains(c) { '_' } else { c })
+        .collect();
+    // Windows drops trailing dots and spaces, which could make two names collide
+    let trimmed = name.trim_end_matches(['.', ' ']).len();
+    name.truncate(trimmed);
+    if name.is_empty() {
+        name.push('_');
+    }
+
+    // Windows also reserves the names with any extension, like nul.tar.gz
+    let base_length = name.find('.').unwrap_or(name.len());
+    if RESERVED_NAMES.iter().any(|reserved| name[..base_length].eq_ignore_ascii_case(reserved)) {
+        name.insert(base_length, '_');
+    }
+
+    let (stem, extension) = match name.rfind('.') {
+        Some(dot) if dot > 0 => name.split_at(dot),
+        _ => (name.as_str(), ""),
+    };
+    let mut stem = stem.to_string();
+
+    // Shorten the stem to fit, keeping the extension and whole characters
+    let extension = if extension.len() > MAX_NAME_LENGTH / 2 { "" } else { extension };
+    while stem.len() + extension.len() > MAX_NAME_LENGTH {
+        stem.pop();
+    }
+    format!("{}{}", stem, extension)
+}
+
+// Writes to a temporary file next to `path` and renames it into place, so an
+// interrupted rebuild never leaves a half written file behind. `path` has to
+// end up inside `root` even after following symlinks.
+pub fn write_atomically(root: &Path, path: &Path, data: &[u8]) -> Result<(), Error> {
+    let mut temp_file = create_atomically(root, path)?;
+    temp_file.write_all(data).map_err(|e| Error::io(format!("Failed to write {}", path.display()), e))?;
+    persist(temp_file, path)
+}
+
+// The temporary file `write_atomically` writes to, for data that arrives in
+// pieces. Hand it to `persist` once it's complete.
+pub fn create_atomically(root: &Path, path: &Path) -> Result<NamedTempFile, Error> {
+    let parent = path.parent().ok_or_else(|| Error::invalid(format!("{} has no parent directory", path.display())))?;
+    create_dir_inside(root, parent)?;
+    NamedTempFile::new_in(parent).map_err(|e| Error::io(format!("Failed to create a file in {}", parent.display()), e))
+}
+
+pub fn persist(temp_file: NamedTempFile, path: &Path) -> Result<(), Error> {
+    temp_file.as_file().sync_all().map_err(|e| Error::io(format!("Failed to save {}", path.display()), e))?;
+    temp_file.persist(path).map_err(|e| Error::io(format!("Failed to save {}", path.display()), e.error))?;
+    Ok(())
+}
+
+// Saves a rebuilt file at `target`. Unless it may replace what's there, a
+// file that appeared since `resolve_conflict` is kept and the rebuilt one
+// gets the next free name, or is dropped for ConflictPolicy::Skip. Returns
+// where it was saved.
+pub fn persist_resolved(temp_file: NamedTempFile, target: &Target, policy: ConflictPolicy) -> Result<Option<PathBuf>, Error> {
+    if target.replace {
+        persist(temp_file, &target.path)?;
+        return Ok(Some(target.path.clone()));
+    }
+    temp_file.as_file().sync_all().map_err(|e| Error::io(format!("Failed to save {}", target.path.display()), e))?;
+    let (mut temp_file, mut path) = (temp_file, target.path.clone());
+    loop {
+        match temp_file.persist_noclobber(&path) {
+            Ok(_) => return Ok(Some(path)),
+            Err(e) if e.error.kind() == io::ErrorKind::AlreadyExists => {
+                if policy == ConflictPolicy::Skip {
+                    return Ok(None);
+                }
+                temp_file = e.file;
+                path = unique_path(&target.path);
+            }
+            Err(e) => return Err(Error::io(format!("Failed to save {}", path.display()), e.error)),
+        }
+    }
+}
+
+// Creates `dir` and its missing parents. The part of it that already exists
+// has to resolve inside `root` before anything is created below it, so a
+// symlink can't lead the new directories elsewhere.
+pub fn create_dir_inside(root: &Path, dir: &Path) -> Result<(), Error> {
+    fs::create_dir_all(root).map_err(|e| Error::io(format!("Failed to create {}", root.display()), e))?;
+    let existing = dir.ancestors().find(|ancestor| ancestor.exists())
+        .ok_or_else(|| Error::invalid(format!("{} has no existing parent directory", dir.display())))?;
+    ensure_inside(root, existing)?;
+    fs::create_dir_all(dir).map_err(|e| Error::io(format!("Failed to create {}", dir.display()), e))?;
+    ensure_inside(root, dir)
+}
+
+// Checks `path`, which has to exist, resolves to somewhere below `root`
+pub fn ensure_inside(root: &Path, path: &Path) -> Result<(), Error> {
+    let root = root.canonicalize().map_err(|e| Error::io(format!("Failed to resolve {}", root.display()), e))?;
+    let resolved = path.canonicalize().map_err(|e| Error::io(format!("Failed to resolve {}", path.display()), e))?;
+    if !resolved.starts_with(&root) {
+        return Err(Error::refused(format!("Refusing to write to {}, it resolves outside {}", path.display(), root.display())));
+    }
+    Ok(())
+}
+
+fn file_name(path: &Path) -> Result<String, Error> {
+    path.file_name()
+        .and_then(|name| name.to_str())
+        .map(|name| name.to_string())
+        .ok_or_else(|| Error::invalid(format!("{} has no usable file name", path.display())))
+}
+
+fn relative_path(root: &Path, path: &Path) -> Result<String, Error> {
+    let relative = path.strip_prefix(root).map_err(|e| Error::invalid(e.to_string()))?;
+    let components: Option<Vec<&str>> = relative.components().map(|c| c.as_os_str().to_str()).collect();
+    components
+        .map(|components| components.join("/"))
+        .ok_or_else(|| Error::invalid(format!("{} is not valid UTF-8", path.display())))
+}
+
+// Shows `dir` in the platform file manager
+pub fn open_in_file_manager(dir: &Path) -> Result<(), Error> {
+    let program = if cfg!(target_os = "windows") {
+        "explorer"
+    } else if cfg!(target_os = "macos") {
+        "open"
+    } else {
+        "xdg-open"
+    };
+    Command::new(program).arg(dir).spawn()
+        .map(|_| ())
+        .map_err(|e| Error::io(format!("Failed to open {}", dir.display()), e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn refused(manifest_path: &str) -> bool {
+        matches!(sanitize_path(manifest_path), Err(Error::Refused(_)))
+    }
+
+    #[test]
+    fn paths_leaving_the_destination_are_refused() {
+        for manifest_path in ["..", "../a.txt", "a/../../b.txt", "a\\..\\b.txt", "/etc/passwd", "\\Windows\\a.txt", "\\\\server\\share\\a.txt", "\\\\?\\C:\\a.txt", "C:", "C:\\a.txt", "c:a.txt", "", "./"] {
+            assert!(refused(manifest_path), "{:?}", manifest_path);
+        }
+    }
+
+    #[test]
+    fn separators_of_both_kinds_split_the_path() {
+        assert_eq!(sanitize_path("a\\b/./c.txt").unwrap(), Path::new("a").join("b").join("c.txt"));
+        // Only a leading drive letter is a prefix, later colons are replaced
+        assert_eq!(sanitize_path("a/C:/b.txt").unwrap(), Path::new("a").join("C_").join("b.txt"));
+    }
+
+    #[test]
+    fn reserved_names_are_renamed_with_any_extension() {
+        assert_eq!(sanitize_path("CON").unwrap(), Path::new("CON_"));
+        assert_eq!(sanitize_path("a/nul.tar.gz").unwrap(), Path::new("a").join("nul_.tar.gz"));
+        assert_eq!(sanitize_path("Com1.txt").unwrap(), Path::new("Com1_.txt"));
+        assert_eq!(sanitize_path("console.txt").unwrap(), Path::new("console.txt"));
+    }
+
+    #[test]
+    fn invalid_characters_and_trailing_dots_are_replaced() {
+        assert_eq!(sanitize_path("a<b>|?*\".txt").unwrap(), Path::new("a_b_____.txt"));
+        assert_eq!(sanitize_path("name. .").unwrap(), Path::new("name"));
+        assert_eq!(sanitize_path("...").unwrap(), Path::new("_"));
+        assert_eq!(sanitize_path("tab\there").unwrap(), Path::new("tab_here"));
+    }
+
+    #[test]
+    fn long_names_are_shortened_keeping_the_extension() {
+        let name = sanitize_path(&format!("{}.txt", "a".repeat(300))).unwrap();
+        let name = name.to_str().unwrap();
+        assert_eq!(name.len(), MAX_NAME_LENGTH);
+        assert!(name.ends_with("a.txt"));
+
+        // Cut at whole characters
+        let name = sanitize_path(&format!("{}.txt", "é".repeat(200))).unwrap();
+        let name = name.to_str().unwrap();
+        assert!(name.len() <= MAX_NAME_LENGTH);
+        assert!(name.ends_with("é.txt"));
+    }
+
+    #[test]
+    fn conflicts_follow_the_policy() {
+        let dir = tempfile::tempdir().unwrap();
+        let free = dir.path().join("free.txt");
+        let taken = dir.path().join("taken.txt");
+        fs::write(&taken, "old").unwrap();
+        let keep = |path: &Path| Some(Target { path: path.to_path_buf(), replace: false });
+        let replace = |path: &Path| Some(Target { path: path.to_path_buf(), replace: true });
+        let unasked = |_: &Path| -> bool { panic!("asked about a free path") };
+
+        assert_eq!(resolve_conflict(&free, ConflictPolicy::Skip, unasked), keep(&free));
+        assert_eq!(resolve_conflict(&free, ConflictPolicy::Overwrite, unasked), replace(&free));
+        assert_eq!(resolve_conflict(&taken, ConflictPolicy::Rename, unasked), keep(&dir.path().join("taken (1).txt")));
+        assert_eq!(resolve_conflict(&taken, ConflictPolicy::Skip, unasked), None);
+        assert_eq!(resolve_conflict(&taken, ConflictPolicy::Overwrite, unasked), replace(&taken));
+        assert_eq!(resolve_conflict(&taken, ConflictPolicy::Ask, |_| true), replace(&taken));
+        assert_eq!(resolve_conflict(&taken, ConflictPolicy::Ask, |_| false), keep(&dir.path().join("taken (1).txt")));
+    }
+
+    #[test]
+    fn files_that_appear_while_rebuilding_are_kept() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("a.txt");
+        let target = resolve_conflict(&path, ConflictPolicy::Rename, |_| false).unwrap();
+        for (policy, saved_to) in [(ConflictPolicy::Rename, Some(dir.path().join("a (1).txt"))), (ConflictPolicy::Skip, None)] {
+            fs::write(&path, "other").unwrap();
+            let mut temp_file = create_atomically(dir.path(), &path).unwrap();
+            temp_file.write_all(b"rebuilt").unwrap();
+            assert_eq!(persist_resolved(temp_file, &target, policy).unwrap(), saved_to);
+            assert_eq!(fs::read_to_string(&path).unwrap(), "other");
+        }
+        assert_eq!(fs::read_to_string(dir.path().join("a (1).txt")).unwrap(), "rebuilt");
+        // Nothing but the two files is left behind
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 2);
+    }
+
+    #[test]
+    fn atomic_writes_replace_the_file_and_leave_nothing_else() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("sub").join("a.txt");
+        write_atomically(dir.path(), &path, b"first").unwrap();
+        write_atomically(dir.path(), &path, b"second").unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "second");
+        assert_eq!(fs::read_dir(path.parent().unwrap()).unwrap().count(), 1);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn nothing_is_created_through_a_symlink_leaving_the_root() {
+        let root = tempfile::tempdir().unwrap();
+        let outside = tempfile::tempdir().unwrap();
+        std::os::unix::fs::symlink(outside.path(), root.path().join("link")).unwrap();
+
+        let path = root.path().join("link").join("new").join("a.txt");
+        assert!(matches!(write_atomically(root.path(), &path, b"data"), Err(Error::Refused(_))));
+        assert!(matches!(create_dir_inside(root.path(), &root.path().join("link").join("empty")), Err(Error::Refused(_))));
+        assert_eq!(fs::read_dir(outside.path()).unwrap().count(), 0);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn collect_inputs_skips_what_isnt_a_file_or_directory() {
+        let root = tempfile::tempdir().unwrap();
+        let picked = root.path().join("picked");
+        fs::create_dir_all(picked.join("empty")).unwrap();
+        fs::write(picked.join("a.txt"), "a").unwrap();
+        let _socket = std::os::unix::net::UnixListener::bind(picked.join("socket")).unwrap();
+        std::os::unix::fs::symlink(picked.join("a.txt"), picked.join("link")).unwrap();
+
+        let inputs = collect_inputs(&[picked.display().to_string()]).unwrap();
+        let names: Vec<&str> = inputs.files.iter().map(|(_, name)| name.as_str()).collect();
+        assert_eq!(names, ["picked/a.txt"]);
+        assert_eq!(inputs.empty_directories, ["picked/empty"]);
+
+        let socket = picked.join("socket").display().to_string();
+        assert!(matches!(collect_inputs(&[socket]), Err(Error::Invalid(_))));
+    }
+}
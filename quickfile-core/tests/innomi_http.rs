@@ -0,0 +1,68 @@
+// Fetches the innomi fixtures from a local server standing in for pst.innomi.net, through the
+// raw endpoint where it answers and the paste page where it doesn't
+
+use std::sync::Arc;
+use reqwest::Client;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpListener;
+use quickfile_core::backend::Backend;
+use quickfile_core::manifest::Manifest;
+use quickfile_core::{history, html, settings, share};
+
+const MANIFEST_PAGE: &str = include_str!("fixtures/innomi/manifest.html");
+const PART_PAGE: &str = include_str!("fixtures/innomi/part.html");
+const PART_RAW: &str = include_str!("fixtures/innomi/part.raw.txt");
+
+// The paste ids, taken from the fixtures so capturing them again needs no changes here
+fn ids() -> (String, String) {
+    let manifest_id = html::paste_title(MANIFEST_PAGE).unwrap();
+    let manifest = Manifest::parse(&html::paste_text(MANIFEST_PAGE).unwrap()).unwrap();
+    (manifest_id, manifest.files[0].parts[0].0[0].id.clone())
+}
+
+// Answers one request per connection. Only the part has a raw answer, like
+// a host that added the endpoint after the manifest was uploaded.
+async fn serve(listener: TcpListener) {
+    let (manifest_id, part_id) = ids();
+    let part_raw_path = format!("{}/raw", part_id);
+    loop {
+        let Ok((mut stream, _)) = listener.accept().await else { return };
+        let (manifest_id, part_id, part_raw_path) = (manifest_id.clone(), part_id.clone(), part_raw_path.clone());
+        tokio::spawn(async move {
+            let mut request = vec![0; 4096];
+            let read = stream.read(&mut request).await.unwrap_or(0);
+            let request = String::from_utf8_lossy(&request[..read]);
+            let path = request.split_whitespace().nth(1).unwrap_or("/");
+            let (status, content_type, body) = match path.strip_prefix("/paste/") {
+                Some(id) if id == part_raw_path => ("200 OK", "text/plain; charset=utf-8", PART_RAW),
+                Some(id) if id == part_id => ("200 OK", "text/html; charset=utf-8", PART_PAGE),
+                Some(id) if id == manifest_id => ("200 OK", "text/html; charset=utf-8", MANIFEST_PAGE),
+                _ => ("404 Not Found", "text/html; charset=utf-8", "<html><body>Not found</body></html>"),
+            };
+            let answer = format!("HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}", status, content_type, body.len(), body);
+            let _ = stream.write_all(answer.as_bytes()).await;
+        });
+    }
+}
+
+#[tokio::test]
+async fn reads_the_raw_answer_and_falls_back_to_the_page() {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let endpoint = format!("http://{}", listener.local_addr().unwrap());
+    tokio::spawn(serve(listener));
+
+    // Point innomi at the server before anything reads the settings, and keep
+    // the settings and history away from the user's
+    let home = tempfile::tempdir().unwrap();
+    std::fs::write(home.path().join("settings.json"), format!("{{\"endpoints\": {{\"innomi\": \"{}\"}}}}", endpoint)).unwrap();
+    settings::use_settings_dir(home.path().to_path_buf());
+    history::use_history_dir(home.path().to_path_buf());
+
+    let (manifest_id, part_id) = ids();
+    let client = Client::new();
+    assert_eq!(Backend::Innomi.fetch_paste(&client, &part_id).await.unwrap(), PART_RAW);
+
+    let manifest = share::download_manifest(Arc::new(client), &manifest_id).await.unwrap();
+    assert_eq!(manifest.files[0].path, "notes/<draft> & final.txt");
+    assert_eq!(manifest.files[0].parts[0].0[0].id, part_id);
+}
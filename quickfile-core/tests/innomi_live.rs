@@ -0,0 +1,66 @@
+// Talks to pst.innomi.net itself, so it only runs when asked:
+//
+//     cargo test -p quickfile-core --test innomi_live -- --ignored
+//
+// With QUICKFILE_CAPTURE set it also saves the pages and raw answers the site gave as the
+// fixtures in tests/fixtures/innomi, which the offline innomi tests read. Capture again when
+// the site changes its layout.
+
+use std::path::Path;
+use std::sync::Arc;
+use reqwest::Client;
+use quickfile_core::backend::Backend;
+use quickfile_core::encoding::Encoding;
+use quickfile_core::error::Error;
+use quickfile_core::{history, html, settings, share};
+use quickfile_core::share::UploadOptions;
+
+const PART_CONTENT: &[u8] = b"<draft> & final?\n";
+// Ids innomi never hands out, they are shorter than its own
+const MISSING_ID: &str = "0000";
+
+async fn get(client: &Client, url: &str) -> String {
+    let response = client.get(url).send().await.unwrap();
+    assert!(response.status().is_success(), "{} answered {}", url, response.status());
+    response.text().await.unwrap()
+}
+
+#[tokio::test]
+#[ignore = "uploads to pst.innomi.net"]
+async fn uploads_and_reads_back_a_share() {
+    let home = tempfile::tempdir().unwrap();
+    settings::use_settings_dir(home.path().join("config"));
+    history::use_history_dir(home.path().join("data"));
+
+    let notes = home.path().join("notes");
+    std::fs::create_dir(&notes).unwrap();
+    std::fs::write(notes.join("<draft> & final.txt"), PART_CONTENT).unwrap();
+    let options = UploadOptions { encoding: Some(Encoding::Base64), backends: vec![Backend::Innomi], ..Default::default() };
+    let sent = share::send(&[notes.display().to_string()], &options).await.unwrap();
+    let part_id = sent.manifest.files[0].parts[0].0[0].id.clone();
+
+    let client = Client::new();
+    let manifest_page = get(&client, &Backend::Innomi.paste_url(&sent.link)).await;
+    let part_page = get(&client, &Backend::Innomi.paste_url(&part_id)).await;
+    let part_raw = get(&client, &Backend::Innomi.raw_url(&part_id).unwrap()).await;
+
+    // What the pipeline reads has to match what was sent
+    assert_eq!(html::paste_title(&manifest_page).unwrap(), sent.link);
+    assert_eq!(html::paste_title(&part_page).unwrap(), part_id);
+    assert_eq!(html::paste_text(&part_page).unwrap(), part_raw);
+    assert_eq!(Backend::Innomi.fetch_paste(&client, &part_id).await.unwrap(), part_raw);
+    assert_eq!(Encoding::Base64.decode(&part_raw).unwrap(), PART_CONTENT);
+    let manifest = share::download_manifest(Arc::new(client.clone()), &sent.link).await.unwrap();
+    assert_eq!(manifest.files[0].path, "notes/<draft> & final.txt");
+
+    // Gone pastes read as an expired share
+    let missing = share::download_manifest(Arc::new(client), MISSING_ID).await;
+    assert!(matches!(missing, Err(Error::Expired(_))), "{:?}", missing.err());
+
+    if std::env::var_os("QUICKFILE_CAPTURE").is_some() {
+        let fixtures = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/innomi");
+        std::fs::write(fixtures.join("manifest.html"), manifest_page).unwrap();
+        std::fs::write(fixtures.join("part.html"), part_page).unwrap();
+        std::fs::write(fixtures.join("part.raw.txt"), part_raw).unwrap();
+    }
+}
@@ -0,0 +1,44 @@
+// Pages pst.innomi.net serves, checked against what the pipeline reads out of them. The
+// fixtures hold a one file share and are saved by the innomi_live test, capture them again
+// there when the layout changes.
+
+use quickfile_core::encoding::Encoding;
+use quickfile_core::error::Error;
+use quickfile_core::html;
+use quickfile_core::manifest::Manifest;
+
+const MANIFEST_PAGE: &str = include_str!("fixtures/innomi/manifest.html");
+const PART_PAGE: &str = include_str!("fixtures/innomi/part.html");
+const PART_RAW: &str = include_str!("fixtures/innomi/part.raw.txt");
+const CHANGED_LAYOUT_PAGE: &str = include_str!("fixtures/innomi/changed_layout.html");
+
+#[test]
+fn manifest_page_gives_back_the_json_unchanged() {
+    let text = html::paste_text(MANIFEST_PAGE).unwrap();
+    assert!(text.starts_with("{\n  \"version\": 4,"));
+    let manifest = Manifest::parse(&text).unwrap();
+    assert_eq!(manifest.files[0].path, "notes/<draft> & final.txt");
+    assert_eq!(manifest.files[0].encoding, Encoding::Base64);
+}
+
+#[test]
+fn part_page_and_raw_answer_hold_the_same_part() {
+    let text = html::paste_text(PART_PAGE).unwrap();
+    assert_eq!(text, PART_RAW);
+    let manifest = Manifest::parse(&html::paste_text(MANIFEST_PAGE).unwrap()).unwrap();
+    let data = manifest.files[0].encoding.decode(&text).unwrap();
+    assert_eq!(data, b"<draft> & final?\n");
+    assert_eq!(Some(data.len() as u64), manifest.files[0].size);
+}
+
+#[test]
+fn part_title_is_the_id_the_manifest_lists() {
+    let manifest = Manifest::parse(&html::paste_text(MANIFEST_PAGE).unwrap()).unwrap();
+    assert_eq!(html::paste_title(PART_PAGE).unwrap(), manifest.files[0].parts[0].0[0].id);
+}
+
+#[test]
+fn changed_layout_is_a_parse_error() {
+    assert!(matches!(html::paste_text(CHANGED_LAYOUT_PAGE), Err(Error::ParseHtml(_))));
+    assert!(matches!(html::paste_title(CHANGED_LAYOUT_PAGE), Err(Error::ParseHtml(_))));
+}